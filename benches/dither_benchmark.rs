@@ -1,18 +1,31 @@
-use criterion::{Criterion, black_box, criterion_group, criterion_main};
-use dither_some::dither;
+use criterion::{
+    BenchmarkId, Criterion, black_box, criterion_group, criterion_main,
+};
+use dither_some::dither::{
+    self, ColorMode, DitherAlgoOpts, Quantizer, ScanOrder,
+};
+use dither_some::palette::Palette;
 
 fn dither_atkinson_benchmark(c: &mut Criterion) {
     let width = 1920;
     let height = 1080;
     let mut buf = vec![0u8; (width * height * 3) as usize];
+    let algo = DitherAlgoOpts::Atkinson {
+        quantizer: Quantizer::Uniform {
+            palette_count: 2,
+            mode: ColorMode::Gray,
+        },
+        scan: ScanOrder::Raster,
+    };
 
     c.bench_function("dither_atkinson", |b| {
         b.iter(|| {
-            dither::dither_frame_atkinson(
+            dither::dither_frame(
                 black_box(width),
                 black_box(height),
                 black_box(&mut buf),
-                black_box(2),
+                black_box(&algo),
+                0,
             );
         });
     });
@@ -22,14 +35,98 @@ fn dither_floyd_steinberg_benchmark(c: &mut Criterion) {
     let width = 1920;
     let height = 1080;
     let mut buf = vec![0u8; (width * height * 3) as usize];
+    let algo = DitherAlgoOpts::FsColor {
+        quantizer: Quantizer::Uniform {
+            palette_count: 2,
+            mode: ColorMode::Rgb,
+        },
+        scan: ScanOrder::Raster,
+    };
 
     c.bench_function("dither_floyd_steinberg", |b| {
         b.iter(|| {
-            dither::dither_frame_floyd_steinberg_color(
+            dither::dither_frame(
                 black_box(width),
                 black_box(height),
                 black_box(&mut buf),
-                black_box(2),
+                black_box(&algo),
+                0,
+            );
+        });
+    });
+}
+
+fn spread_palette(count: usize) -> Palette {
+    let colors = (0..count)
+        .map(|i| {
+            (
+                (i * 37 % 256) as f32,
+                (i * 91 % 256) as f32,
+                (i * 151 % 256) as f32,
+            )
+        })
+        .collect();
+    Palette::new(colors).unwrap()
+}
+
+fn palette_nearest_benchmark(c: &mut Criterion) {
+    let mut group = c.benchmark_group("palette_nearest");
+    let pixels: Vec<(f32, f32, f32)> = (0..4096)
+        .map(|i| {
+            (
+                (i % 256) as f32,
+                (i / 16 % 256) as f32,
+                (i * 7 % 256) as f32,
+            )
+        })
+        .collect();
+
+    // Palettes of 16 or fewer colors always search linearly.
+    for count in [17, 32, 64, 256] {
+        let palette = spread_palette(count);
+        group.bench_with_input(
+            BenchmarkId::new("kd_tree", count),
+            &palette,
+            |b, palette| {
+                b.iter(|| {
+                    for &rgb in &pixels {
+                        black_box(palette.nearest(black_box(rgb)));
+                    }
+                });
+            },
+        );
+        group.bench_with_input(
+            BenchmarkId::new("linear", count),
+            &palette,
+            |b, palette| {
+                b.iter(|| {
+                    for &rgb in &pixels {
+                        black_box(palette.nearest_linear(black_box(rgb)));
+                    }
+                });
+            },
+        );
+    }
+    group.finish();
+}
+
+fn dither_floyd_steinberg_palette_benchmark(c: &mut Criterion) {
+    let width = 1920;
+    let height = 1080;
+    let mut buf: Vec<u8> = (0..width * height * 3).map(|i| i as u8).collect();
+    let algo = DitherAlgoOpts::FsColor {
+        quantizer: Quantizer::Palette(spread_palette(256)),
+        scan: ScanOrder::Raster,
+    };
+
+    c.bench_function("dither_floyd_steinberg_palette_256", |b| {
+        b.iter(|| {
+            dither::dither_frame(
+                black_box(width),
+                black_box(height),
+                black_box(&mut buf),
+                black_box(&algo),
+                0,
             );
         });
     });
@@ -42,6 +139,7 @@ fn custom_criterion() -> Criterion {
 criterion_group! {
     name = dither_benchmark;
     config = custom_criterion();
-    targets = dither_atkinson_benchmark, dither_floyd_steinberg_benchmark
+    targets = dither_atkinson_benchmark, dither_floyd_steinberg_benchmark,
+        palette_nearest_benchmark, dither_floyd_steinberg_palette_benchmark
 }
 criterion_main!(dither_benchmark);
@@ -1,8 +1,14 @@
-use std::str::FromStr;
+use clap::builder::{PossibleValuesParser, TypedValueParser};
+use clap::{Args, Parser, Subcommand, ValueEnum};
 
-use clap::{Parser, Subcommand};
-
-use crate::frame::Resolution;
+use dither_some::color::ColorMetric;
+use dither_some::dither::{NoiseDistribution, ScanOrder};
+use dither_some::frame::Resolution;
+use dither_some::halftone::DotShape;
+use dither_some::kernel::DiffusionKernel;
+use dither_some::levels::ChannelLevels;
+use dither_some::palette;
+use dither_some::pattern::PatternMethod;
 
 #[derive(Parser, Debug)]
 #[command(version, subcommand_value_name = "ALGORITHM")]
@@ -15,6 +21,85 @@ pub struct CliArgs {
     #[arg(long, allow_hyphen_values = true)]
     pub output_res: Option<Resolution>,
 
+    /// Palette file to dither to instead of evenly spaced levels, unless
+    /// another --color-mode is given. GIMP (.gpl), JASC (.pal), Adobe (.act)
+    /// and hex color per line files are supported, as are inline colors like
+    /// "#000000,#ff8800,#ffffff".
+    #[arg(long)]
+    pub palette: Option<String>,
+
+    /// Built-in palette to dither to, like --palette.
+    #[arg(long, conflicts_with = "palette", value_parser = PossibleValuesParser::new(palette::names()))]
+    pub palette_name: Option<String>,
+
+    /// Generate a palette of this many colors from the input video to dither
+    /// to, like --palette.
+    #[arg(long, conflicts_with_all = ["palette", "palette_name"], value_parser = clap::value_parser!(u32).range(1..=256))]
+    pub generate_palette: Option<u32>,
+
+    /// How --generate-palette derives its colors.
+    #[arg(long, value_enum, requires = "generate_palette", default_value_t = CliPaletteMethod::MedianCut)]
+    pub palette_method: CliPaletteMethod,
+
+    /// Seed of the initial k-means centers.
+    #[arg(long, requires = "generate_palette", default_value_t = 0)]
+    pub palette_seed: u64,
+
+    /// Sample one in this many frames for --generate-palette.
+    #[arg(long, requires = "generate_palette", value_parser = clap::value_parser!(u32).range(1..), default_value_t = 10)]
+    pub palette_sample_every: u32,
+
+    /// Dither in linear light instead of on gamma encoded sRGB values, which
+    /// keeps the brightness of dithered midtones.
+    #[arg(long)]
+    pub linear: bool,
+
+    /// Keep each pixel's previous output while no channel of its source has
+    /// changed by more than this, which stops static regions from flickering.
+    #[arg(long)]
+    pub temporal_tolerance: Option<u8>,
+
+    /// Fraction of each refreshed pixel's quantization error carried into the
+    /// next frame with --temporal-tolerance.
+    #[arg(long, requires = "temporal_tolerance", value_parser = parse_ratio)]
+    pub carry_error: Option<f32>,
+
+    /// Follow motion between frames with --temporal-tolerance, matching
+    /// blocks of this many pixels square, so the output of moving regions
+    /// moves with them instead of being dithered anew.
+    #[arg(long, requires = "temporal_tolerance", value_parser = clap::value_parser!(u32).range(2..))]
+    pub motion_block_size: Option<u32>,
+
+    /// Farthest in pixels a block is searched for with --motion-block-size.
+    #[arg(long, requires = "motion_block_size", value_parser = clap::value_parser!(u32).range(1..), default_value_t = 8)]
+    pub motion_search_range: u32,
+
+    /// Detect scene cuts where the color histograms of consecutive frames
+    /// differ by more than this fraction of pixels, and dither each scene
+    /// from scratch.
+    #[arg(long, value_parser = parse_ratio)]
+    pub scene_threshold: Option<f32>,
+
+    /// Write the cuts found with --scene-threshold to this path as JSON.
+    #[arg(long, requires = "scene_threshold")]
+    pub scene_report: Option<String>,
+
+    /// Generate a palette for each scene found with --scene-threshold
+    /// instead of one for the whole video, with --generate-palette.
+    #[arg(long, requires_all = ["generate_palette", "scene_threshold"])]
+    pub scene_palettes: bool,
+
+    /// Fade each scene's palette in from the previous scene's over this many
+    /// frames with --scene-palettes. Temporal stabilization starts over on
+    /// every frame of a fade, as each has its own colors.
+    #[arg(long, requires = "scene_palettes", default_value_t = 0)]
+    pub palette_transition: u32,
+
+    /// How the nearest palette color is picked: RGB distance, RGB distance
+    /// weighted by redness, CIE76, CIEDE2000 or OKLab distance.
+    #[arg(long, value_enum, default_value_t = CliColorMetric::Rgb)]
+    pub color_metric: CliColorMetric,
+
     /// Path of video to dither.
     #[arg(index = 1)]
     pub input: String,
@@ -29,35 +114,430 @@ pub struct CliArgs {
 
 #[derive(Debug, Subcommand)]
 pub enum CliAlgorithm {
-    /// Apply Atkinson dithering algorithm.
+    /// Apply Atkinson dithering algorithm, in grayscale unless --color is
+    /// given.
     Atkinson {
-        #[arg(short, long, value_parser = clap::value_parser!(u32).range(2..=256), default_value_t = 2)]
-        palette_count: u32,
+        #[command(flatten)]
+        diffusion: DiffusionArgs,
     },
 
-    /// Apply colored Floyd-Steinberg dithering algorithm.
+    /// Apply Floyd-Steinberg dithering algorithm.
+    #[command(visible_alias = "fs")]
     FsColor {
-        #[arg(short, long, value_parser = clap::value_parser!(u32).range(2..=256), default_value_t = 2)]
-        palette_count: u32,
+        #[command(flatten)]
+        diffusion: DiffusionArgs,
+    },
+
+    /// Apply Jarvis-Judice-Ninke dithering algorithm.
+    #[command(visible_alias = "jjn")]
+    JarvisJudiceNinke {
+        #[command(flatten)]
+        diffusion: DiffusionArgs,
+    },
+
+    /// Apply Stucki dithering algorithm.
+    Stucki {
+        #[command(flatten)]
+        diffusion: DiffusionArgs,
+    },
+
+    /// Apply Burkes dithering algorithm.
+    Burkes {
+        #[command(flatten)]
+        diffusion: DiffusionArgs,
+    },
+
+    /// Apply Sierra dithering algorithm.
+    Sierra {
+        #[command(flatten)]
+        diffusion: DiffusionArgs,
+    },
+
+    /// Apply Two-Row Sierra dithering algorithm.
+    TwoRowSierra {
+        #[command(flatten)]
+        diffusion: DiffusionArgs,
+    },
+
+    /// Apply Sierra Lite dithering algorithm.
+    SierraLite {
+        #[command(flatten)]
+        diffusion: DiffusionArgs,
+    },
+
+    /// Apply Ostromoukhov variable-coefficient dithering algorithm,
+    /// best used with --serpentine.
+    Ostromoukhov {
+        #[command(flatten)]
+        diffusion: DiffusionArgs,
+    },
+
+    /// Apply Zhou-Fang variable-coefficient dithering algorithm with
+    /// threshold modulation, best used with --serpentine.
+    ZhouFang {
+        #[command(flatten)]
+        diffusion: DiffusionArgs,
+    },
+
+    /// Apply error diffusion dithering with a user-defined kernel.
+    CustomDiffusion {
+        /// Kernel rows separated by '/', starting with '*' for the current
+        /// pixel and ending with the divisor, e.g. "* 7 / 3 5 1 / 16". The
+        /// first row only has weights right of '*', as pixels left of it are
+        /// already quantized. Rows below the first are centered under '*'.
+        #[arg(short, long, value_parser = DiffusionKernel::parse, required_unless_present = "kernel_file", conflicts_with = "kernel_file")]
+        kernel: Option<DiffusionKernel>,
+
+        /// Path of a file containing a kernel in the same format as --kernel,
+        /// rows may be separated by newlines instead of '/'. Other formats,
+        /// like TOML, aren't supported.
+        #[arg(long)]
+        kernel_file: Option<String>,
+
+        #[command(flatten)]
+        diffusion: DiffusionArgs,
+    },
+
+    /// Apply Knuth's dot diffusion, processing each class of pixels in
+    /// parallel.
+    DotDiffusion {
+        /// Path of a class matrix file, whitespace separated classes from 0 to
+        /// N-1 with one row per line. Defaults to Knuth's 8x8 matrix.
+        #[arg(short, long)]
+        class_matrix: Option<String>,
+
+        #[command(flatten)]
+        quantize: QuantizeArgs,
+    },
+
+    /// Apply ordered dithering with a Bayer threshold matrix.
+    Bayer {
+        /// Width and height of the threshold matrix.
+        #[arg(short, long, value_parser = bayer_size_parser(), default_value = "8")]
+        size: usize,
+
+        #[command(flatten)]
+        quantize: QuantizeArgs,
+    },
+
+    /// Apply ordered dithering with a blue-noise threshold mask.
+    BlueNoise {
+        /// Width and height of the generated mask. Generation is slow beyond
+        /// 64, load a larger mask with --mask instead.
+        #[arg(short, long, value_parser = clap::value_parser!(u32).range(8..=64), default_value_t = 64)]
+        size: u32,
+
+        /// Seed of the generated mask.
+        #[arg(long, default_value_t = 0)]
+        seed: u64,
+
+        /// Path of a grayscale image to use as the mask instead of generating
+        /// one.
+        #[arg(short, long, conflicts_with_all = ["size", "seed"])]
+        mask: Option<String>,
+
+        #[command(flatten)]
+        quantize: QuantizeArgs,
+    },
+
+    /// Apply ordered dithering against the palette by mixing its colors in
+    /// patterns.
+    Pattern {
+        #[arg(short, long, value_enum, default_value_t = CliPatternMethod::Yliluoma2)]
+        method: CliPatternMethod,
+
+        /// Width and height of the Bayer matrix that picks from the mixed
+        /// colors.
+        #[arg(short, long, value_parser = bayer_size_parser(), default_value = "8")]
+        size: usize,
+
+        /// Path of a grayscale image to use as the threshold matrix instead.
+        #[arg(long, conflicts_with = "size")]
+        mask: Option<String>,
+    },
+
+    /// Apply clustered-dot halftoning, in CMYK unless --gray is given.
+    Halftone {
+        #[arg(long, value_enum, default_value_t = CliDotShape::Round)]
+        shape: CliDotShape,
+
+        /// Size of a halftone cell in pixels, i.e. the inverse of the screen
+        /// frequency.
+        #[arg(short, long, value_parser = parse_cell_size, default_value_t = 6.0)]
+        cell_size: f32,
+
+        /// Screen the luma with a single black screen.
+        #[arg(long)]
+        gray: bool,
+
+        /// Angle in degrees of the black screen, the only screen with --gray.
+        #[arg(long, default_value_t = 45.0, allow_hyphen_values = true)]
+        angle: f32,
+
+        /// Angle in degrees of the cyan screen.
+        #[arg(long, default_value_t = 15.0, allow_hyphen_values = true)]
+        cyan_angle: f32,
+
+        /// Angle in degrees of the magenta screen.
+        #[arg(long, default_value_t = 75.0, allow_hyphen_values = true)]
+        magenta_angle: f32,
+
+        /// Angle in degrees of the yellow screen.
+        #[arg(long, default_value_t = 0.0, allow_hyphen_values = true)]
+        yellow_angle: f32,
+    },
+
+    /// Refine another algorithm's output with direct binary search. Slow, meant
+    /// for stills and poster frames.
+    Dbs {
+        /// Algorithm whose output is refined.
+        #[arg(short, long, value_enum, default_value_t = CliDbsInitial::Atkinson)]
+        initial: CliDbsInitial,
+
+        /// Maximum number of passes over the frame.
+        #[arg(long, value_parser = clap::value_parser!(u32).range(1..), default_value_t = 10)]
+        max_iterations: u32,
+
+        /// Stop once a pass changes at most this fraction of pixels.
+        #[arg(long, value_parser = parse_ratio, default_value_t = 0.001)]
+        convergence: f32,
+
+        #[command(flatten)]
+        quantize: QuantizeArgs,
+    },
+
+    /// Threshold every pixel against seeded random noise.
+    Random {
+        #[arg(short, long, value_enum, default_value_t = CliNoiseDistribution::Uniform)]
+        distribution: CliNoiseDistribution,
+
+        /// Seed of the noise, the same seed always gives the same output.
+        #[arg(long, default_value_t = 0)]
+        seed: u64,
+
+        /// Draw new noise every frame instead of holding it static.
+        #[arg(long)]
+        reseed_per_frame: bool,
+
+        #[command(flatten)]
+        quantize: QuantizeArgs,
+    },
+
+    /// Apply Riemersma dithering along a Hilbert curve.
+    Riemersma {
+        /// Number of previous errors along the curve that are diffused.
+        #[arg(short, long, value_parser = clap::value_parser!(u32).range(2..=256), default_value_t = 16)]
+        queue_length: u32,
+
+        /// Weight of the oldest error relative to the newest one.
+        #[arg(short, long, value_parser = parse_ratio, default_value_t = 1.0 / 16.0)]
+        ratio: f32,
+
+        #[command(flatten)]
+        quantize: QuantizeArgs,
     },
 }
 
-impl FromStr for Resolution {
-    type Err = String;
+impl CliAlgorithm {
+    /// Quantization options of the algorithm, if it has any.
+    pub fn quantize_args(&self) -> Option<&QuantizeArgs> {
+        match self {
+            CliAlgorithm::Atkinson { diffusion }
+            | CliAlgorithm::FsColor { diffusion }
+            | CliAlgorithm::JarvisJudiceNinke { diffusion }
+            | CliAlgorithm::Stucki { diffusion }
+            | CliAlgorithm::Burkes { diffusion }
+            | CliAlgorithm::Sierra { diffusion }
+            | CliAlgorithm::TwoRowSierra { diffusion }
+            | CliAlgorithm::SierraLite { diffusion }
+            | CliAlgorithm::Ostromoukhov { diffusion }
+            | CliAlgorithm::ZhouFang { diffusion }
+            | CliAlgorithm::CustomDiffusion { diffusion, .. } => {
+                Some(&diffusion.quantize)
+            }
+            CliAlgorithm::DotDiffusion { quantize, .. }
+            | CliAlgorithm::Bayer { quantize, .. }
+            | CliAlgorithm::BlueNoise { quantize, .. }
+            | CliAlgorithm::Dbs { quantize, .. }
+            | CliAlgorithm::Random { quantize, .. }
+            | CliAlgorithm::Riemersma { quantize, .. } => Some(quantize),
+            CliAlgorithm::Pattern { .. } | CliAlgorithm::Halftone { .. } => {
+                None
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+pub enum CliPaletteMethod {
+    MedianCut,
+    Octree,
+    KMeans,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, ValueEnum)]
+pub enum CliColorMode {
+    Gray,
+    Rgb,
+    Palette,
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+pub enum CliColorMetric {
+    Rgb,
+    Redmean,
+    Lab,
+    Ciede2000,
+    Oklab,
+}
+
+impl From<CliColorMetric> for ColorMetric {
+    fn from(metric: CliColorMetric) -> Self {
+        match metric {
+            CliColorMetric::Rgb => ColorMetric::Rgb,
+            CliColorMetric::Redmean => ColorMetric::Redmean,
+            CliColorMetric::Lab => ColorMetric::Lab,
+            CliColorMetric::Ciede2000 => ColorMetric::Ciede2000,
+            CliColorMetric::Oklab => ColorMetric::Oklab,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+pub enum CliDbsInitial {
+    /// Quantize every pixel to its nearest level.
+    Threshold,
+    Atkinson,
+    FloydSteinberg,
+    Bayer,
+    BlueNoise,
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+pub enum CliNoiseDistribution {
+    Uniform,
+    Triangular,
+}
 
-    fn from_str(s: &str) -> Result<Self, Self::Err> {
-        let parts: Vec<&str> = s.split('x').collect();
-        if parts.len() != 2 {
-            return Err(format!("Expected WIDTHxHEIGHT, got '{}'", s));
+impl From<CliNoiseDistribution> for NoiseDistribution {
+    fn from(distribution: CliNoiseDistribution) -> Self {
+        match distribution {
+            CliNoiseDistribution::Uniform => NoiseDistribution::Uniform,
+            CliNoiseDistribution::Triangular => NoiseDistribution::Triangular,
         }
+    }
+}
 
-        let width = parts[0]
-            .parse::<isize>()
-            .map_err(|_| "Invalid width".to_string())?;
-        let height = parts[1]
-            .parse::<isize>()
-            .map_err(|_| "Invalid width".to_string())?;
+#[derive(Debug, Clone, Copy, ValueEnum)]
+pub enum CliPatternMethod {
+    Yliluoma1,
+    Yliluoma2,
+    Knoll,
+}
 
-        Ok(Resolution::new(width, height))
+impl From<CliPatternMethod> for PatternMethod {
+    fn from(method: CliPatternMethod) -> Self {
+        match method {
+            CliPatternMethod::Yliluoma1 => PatternMethod::Yliluoma1,
+            CliPatternMethod::Yliluoma2 => PatternMethod::Yliluoma2,
+            CliPatternMethod::Knoll => PatternMethod::Knoll,
+        }
     }
 }
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+pub enum CliDotShape {
+    Round,
+    Ellipse,
+    Line,
+    Square,
+}
+
+impl From<CliDotShape> for DotShape {
+    fn from(shape: CliDotShape) -> Self {
+        match shape {
+            CliDotShape::Round => DotShape::Round,
+            CliDotShape::Ellipse => DotShape::Ellipse,
+            CliDotShape::Line => DotShape::Line,
+            CliDotShape::Square => DotShape::Square,
+        }
+    }
+}
+
+fn parse_ratio(s: &str) -> Result<f32, String> {
+    match s.parse::<f32>() {
+        Ok(ratio) if 0.0 < ratio && ratio <= 1.0 => Ok(ratio),
+        _ => Err("Expected a ratio in (0, 1]".to_string()),
+    }
+}
+
+fn parse_cell_size(s: &str) -> Result<f32, String> {
+    match s.parse::<f32>() {
+        Ok(size) if size >= 2.0 && size.is_finite() => Ok(size),
+        _ => Err("Expected a cell size of at least 2".to_string()),
+    }
+}
+
+#[derive(Debug, Args)]
+pub struct DiffusionArgs {
+    #[command(flatten)]
+    pub quantize: QuantizeArgs,
+
+    /// Alternate scan direction every row to break up diagonal artifacts.
+    #[arg(long)]
+    pub serpentine: bool,
+}
+
+impl DiffusionArgs {
+    pub fn scan_order(&self) -> ScanOrder {
+        if self.serpentine {
+            ScanOrder::Serpentine
+        } else {
+            ScanOrder::Raster
+        }
+    }
+}
+
+#[derive(Debug, Args)]
+pub struct QuantizeArgs {
+    #[arg(short, long, value_parser = clap::value_parser!(u32).range(2..=256), default_value_t = 2)]
+    pub palette_count: u32,
+
+    /// Per channel levels instead of --palette-count, as bits per channel like
+    /// rgb332 or rgb565, level counts like 6x7x6, or level tables like
+    /// 0,96,255 for every channel or 0,255/0,128,255/0,255 for R, G and B.
+    /// Gray output only uses the levels every channel has.
+    #[arg(long, conflicts_with = "palette_count", value_parser = ChannelLevels::parse)]
+    pub levels: Option<ChannelLevels>,
+
+    /// Quantize luma to gray levels, every RGB channel to its levels, or
+    /// pixels to the nearest palette color. Defaults to palette when a palette
+    /// is given, otherwise to rgb, or gray for atkinson.
+    #[arg(long, value_enum)]
+    pub color_mode: Option<CliColorMode>,
+
+    /// Shorthand for --color-mode gray.
+    #[arg(long, conflicts_with_all = ["color_mode", "color"])]
+    pub gray: bool,
+
+    /// Shorthand for --color-mode rgb.
+    #[arg(long, conflicts_with = "color_mode")]
+    pub color: bool,
+}
+
+impl QuantizeArgs {
+    pub fn color_mode(&self) -> Option<CliColorMode> {
+        if self.gray {
+            Some(CliColorMode::Gray)
+        } else if self.color {
+            Some(CliColorMode::Rgb)
+        } else {
+            self.color_mode
+        }
+    }
+}
+
+fn bayer_size_parser() -> impl TypedValueParser<Value = usize> {
+    PossibleValuesParser::new(["2", "4", "8", "16"])
+        .map(|s| s.parse::<usize>().unwrap())
+}
@@ -0,0 +1,347 @@
+use crate::frame::{GrayPixel, RgbPixel};
+
+/// A color in CIELAB or OKLab, as L, a and b.
+pub type Lab = (f32, f32, f32);
+
+/// Rec. 601 luma of an RGB pixel.
+pub fn luma((r, g, b): RgbPixel) -> GrayPixel {
+    0.299 * r + 0.587 * g + 0.114 * b
+}
+
+/// Decode an sRGB channel in [0, 1] to linear light in [0, 1].
+pub fn srgb_to_linear(c: f32) -> f32 {
+    if c <= 0.04045 {
+        c / 12.92
+    } else {
+        ((c + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+/// Encode a linear light channel in [0, 1] to sRGB in [0, 1].
+pub fn linear_to_srgb(c: f32) -> f32 {
+    if c <= 0.0031308 {
+        c * 12.92
+    } else {
+        1.055 * c.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+/// Linear light of an sRGB pixel in [0, 255], with channels in [0, 1].
+pub fn rgb_to_linear((r, g, b): RgbPixel) -> (f32, f32, f32) {
+    (
+        srgb_to_linear(r / 255.0),
+        srgb_to_linear(g / 255.0),
+        srgb_to_linear(b / 255.0),
+    )
+}
+
+/// Decode an sRGB pixel to linear light, both with channels in [0, 255].
+pub fn decode_srgb((r, g, b): RgbPixel) -> RgbPixel {
+    let decode = |c: f32| srgb_to_linear(c / 255.0) * 255.0;
+    (decode(r), decode(g), decode(b))
+}
+
+/// Encode a linear light pixel to sRGB, both with channels in [0, 255].
+pub fn encode_srgb((r, g, b): RgbPixel) -> RgbPixel {
+    let encode = |c: f32| linear_to_srgb(c / 255.0) * 255.0;
+    (encode(r), encode(g), encode(b))
+}
+
+/// CIE XYZ of an sRGB pixel in [0, 255] under D65, with Y in [0, 1].
+pub fn rgb_to_xyz(rgb: RgbPixel) -> (f32, f32, f32) {
+    let (r, g, b) = rgb_to_linear(rgb);
+    (
+        0.4124564 * r + 0.3575761 * g + 0.1804375 * b,
+        0.2126729 * r + 0.7151522 * g + 0.0721750 * b,
+        0.0193339 * r + 0.119192 * g + 0.9503041 * b,
+    )
+}
+
+/// CIELAB of an sRGB pixel in [0, 255], relative to the D65 white point.
+pub fn rgb_to_lab(rgb: RgbPixel) -> Lab {
+    const WHITE: (f32, f32, f32) = (0.95047, 1.0, 1.08883);
+    const DELTA: f32 = 6.0 / 29.0;
+
+    let f = |t: f32| {
+        if t > DELTA.powi(3) {
+            t.cbrt()
+        } else {
+            t / (3.0 * DELTA * DELTA) + 4.0 / 29.0
+        }
+    };
+    let (x, y, z) = rgb_to_xyz(rgb);
+    let (fx, fy, fz) = (f(x / WHITE.0), f(y / WHITE.1), f(z / WHITE.2));
+
+    (116.0 * fy - 16.0, 500.0 * (fx - fy), 200.0 * (fy - fz))
+}
+
+/// OKLab of an sRGB pixel in [0, 255], with L in [0, 1].
+pub fn rgb_to_oklab(rgb: RgbPixel) -> Lab {
+    let (r, g, b) = rgb_to_linear(rgb);
+    let l = (0.41222147 * r + 0.53633254 * g + 0.051445993 * b).cbrt();
+    let m = (0.2119035 * r + 0.6806995 * g + 0.10739696 * b).cbrt();
+    let s = (0.08830246 * r + 0.28171884 * g + 0.6299787 * b).cbrt();
+
+    (
+        0.21045426 * l + 0.7936178 * m - 0.004072047 * s,
+        1.9779985 * l - 2.4285922 * m + 0.4505937 * s,
+        0.025904037 * l + 0.78277177 * m - 0.80867577 * s,
+    )
+}
+
+/// CIE76 color difference, the euclidean distance in CIELAB.
+pub fn delta_e76((l1, a1, b1): Lab, (l2, a2, b2): Lab) -> f32 {
+    ((l1 - l2).powi(2) + (a1 - a2).powi(2) + (b1 - b2).powi(2)).sqrt()
+}
+
+/// CIEDE2000 color difference, with unit weighting factors.
+pub fn ciede2000((l1, a1, b1): Lab, (l2, a2, b2): Lab) -> f32 {
+    let (l1, a1, b1) = (l1 as f64, a1 as f64, b1 as f64);
+    let (l2, a2, b2) = (l2 as f64, a2 as f64, b2 as f64);
+    let pow7 = |v: f64| v.powi(7);
+    let hue = |b: f64, a: f64| {
+        if a == 0.0 && b == 0.0 {
+            0.0
+        } else {
+            b.atan2(a).to_degrees().rem_euclid(360.0)
+        }
+    };
+
+    let c_bar = (a1.hypot(b1) + a2.hypot(b2)) / 2.0;
+    let g = 0.5 * (1.0 - (pow7(c_bar) / (pow7(c_bar) + pow7(25.0))).sqrt());
+    let (a1, a2) = ((1.0 + g) * a1, (1.0 + g) * a2);
+    let (c1, c2) = (a1.hypot(b1), a2.hypot(b2));
+    let (h1, h2) = (hue(b1, a1), hue(b2, a2));
+
+    let delta_l = l2 - l1;
+    let delta_c = c2 - c1;
+    let delta_h = if c1 * c2 == 0.0 {
+        0.0
+    } else if (h2 - h1).abs() <= 180.0 {
+        h2 - h1
+    } else if h2 - h1 > 180.0 {
+        h2 - h1 - 360.0
+    } else {
+        h2 - h1 + 360.0
+    };
+    let delta_h = 2.0 * (c1 * c2).sqrt() * (delta_h.to_radians() / 2.0).sin();
+
+    let l_bar = (l1 + l2) / 2.0;
+    let c_bar = (c1 + c2) / 2.0;
+    let h_bar = if c1 * c2 == 0.0 {
+        h1 + h2
+    } else if (h1 - h2).abs() <= 180.0 {
+        (h1 + h2) / 2.0
+    } else if h1 + h2 < 360.0 {
+        (h1 + h2 + 360.0) / 2.0
+    } else {
+        (h1 + h2 - 360.0) / 2.0
+    };
+
+    let cos = |degrees: f64| degrees.to_radians().cos();
+    let t = 1.0 - 0.17 * cos(h_bar - 30.0)
+        + 0.24 * cos(2.0 * h_bar)
+        + 0.32 * cos(3.0 * h_bar + 6.0)
+        - 0.20 * cos(4.0 * h_bar - 63.0);
+    let delta_theta = 30.0 * (-((h_bar - 275.0) / 25.0).powi(2)).exp();
+    let r_c = 2.0 * (pow7(c_bar) / (pow7(c_bar) + pow7(25.0))).sqrt();
+    let s_l = 1.0
+        + 0.015 * (l_bar - 50.0).powi(2)
+            / (20.0 + (l_bar - 50.0).powi(2)).sqrt();
+    let s_c = 1.0 + 0.045 * c_bar;
+    let s_h = 1.0 + 0.015 * c_bar * t;
+    let r_t = -(2.0 * delta_theta).to_radians().sin() * r_c;
+
+    let (l, c, h) = (delta_l / s_l, delta_c / s_c, delta_h / s_h);
+    (l * l + c * c + h * h + r_t * c * h).sqrt() as f32
+}
+
+/// "Redmean" distance, an RGB distance weighted by how red the colors are
+/// that approximates perceived difference without leaving RGB.
+pub fn redmean((r1, g1, b1): RgbPixel, (r2, g2, b2): RgbPixel) -> f32 {
+    let r_bar = (r1 + r2) / 2.0;
+    ((2.0 + r_bar / 256.0) * (r1 - r2).powi(2)
+        + 4.0 * (g1 - g2).powi(2)
+        + (2.0 + (255.0 - r_bar) / 256.0) * (b1 - b2).powi(2))
+    .sqrt()
+}
+
+/// How the distance between two colors is measured when picking the nearest
+/// palette color.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ColorMetric {
+    /// Euclidean distance in sRGB.
+    Rgb,
+    Redmean,
+    /// CIE76, the euclidean distance in CIELAB.
+    Lab,
+    Ciede2000,
+    /// Euclidean distance in OKLab.
+    Oklab,
+}
+
+impl ColorMetric {
+    /// Convert an sRGB pixel to the space this metric compares colors in, so
+    /// that colors compared many times are only converted once.
+    pub fn convert(&self, rgb: RgbPixel) -> (f32, f32, f32) {
+        match self {
+            ColorMetric::Rgb | ColorMetric::Redmean => rgb,
+            ColorMetric::Lab | ColorMetric::Ciede2000 => rgb_to_lab(rgb),
+            ColorMetric::Oklab => rgb_to_oklab(rgb),
+        }
+    }
+
+    /// Whether compare is the squared euclidean distance between converted
+    /// colors.
+    pub fn is_euclidean(&self) -> bool {
+        matches!(
+            self,
+            ColorMetric::Rgb | ColorMetric::Lab | ColorMetric::Oklab
+        )
+    }
+
+    /// A value that grows with the distance between two converted colors. For
+    /// euclidean metrics this is the squared distance.
+    pub fn compare(&self, a: (f32, f32, f32), b: (f32, f32, f32)) -> f32 {
+        match self {
+            ColorMetric::Rgb | ColorMetric::Lab | ColorMetric::Oklab => {
+                (a.0 - b.0).powi(2) + (a.1 - b.1).powi(2) + (a.2 - b.2).powi(2)
+            }
+            ColorMetric::Redmean => redmean(a, b),
+            ColorMetric::Ciede2000 => ciede2000(a, b),
+        }
+    }
+
+    /// Distance between two converted colors, like compare but never squared.
+    pub fn distance(&self, a: (f32, f32, f32), b: (f32, f32, f32)) -> f32 {
+        match self {
+            ColorMetric::Rgb | ColorMetric::Lab | ColorMetric::Oklab => {
+                self.compare(a, b).sqrt()
+            }
+            ColorMetric::Redmean | ColorMetric::Ciede2000 => self.compare(a, b),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(
+        actual: (f32, f32, f32),
+        expected: (f32, f32, f32),
+        e: f32,
+    ) {
+        assert!(
+            (actual.0 - expected.0).abs() < e
+                && (actual.1 - expected.1).abs() < e
+                && (actual.2 - expected.2).abs() < e,
+            "{:?} != {:?}",
+            actual,
+            expected
+        );
+    }
+
+    #[test]
+    fn srgb_transfer_round_trips() {
+        assert!((srgb_to_linear(0.5) - 0.214041).abs() < 1e-5);
+        assert!((linear_to_srgb(0.5) - 0.735357).abs() < 1e-5);
+        for i in 0..=255 {
+            let c = i as f32 / 255.0;
+            assert!((linear_to_srgb(srgb_to_linear(c)) - c).abs() < 1e-5);
+        }
+    }
+
+    #[test]
+    fn srgb_pixels_round_trip() {
+        for i in 0..=255 {
+            let v = i as f32;
+            let (r, g, b) = encode_srgb(decode_srgb((v, 0.0, 255.0)));
+            assert_eq!((r.round(), g.round(), b.round()), (v, 0.0, 255.0));
+        }
+        assert!((decode_srgb((128.0, 0.0, 0.0)).0 - 55.0).abs() < 0.1);
+    }
+
+    #[test]
+    fn rgb_to_lab_matches_reference() {
+        assert_close(
+            rgb_to_lab((255.0, 255.0, 255.0)),
+            (100.0, 0.0, 0.0),
+            0.01,
+        );
+        assert_close(rgb_to_lab((0.0, 0.0, 0.0)), (0.0, 0.0, 0.0), 0.01);
+        assert_close(
+            rgb_to_lab((255.0, 0.0, 0.0)),
+            (53.2408, 80.0925, 67.2032),
+            0.05,
+        );
+        assert_close(
+            rgb_to_lab((0.0, 0.0, 255.0)),
+            (32.2970, 79.1875, -107.8602),
+            0.05,
+        );
+    }
+
+    #[test]
+    fn rgb_to_oklab_matches_reference() {
+        assert_close(
+            rgb_to_oklab((255.0, 255.0, 255.0)),
+            (1.0, 0.0, 0.0),
+            1e-3,
+        );
+        assert_close(
+            rgb_to_oklab((255.0, 0.0, 0.0)),
+            (0.627955, 0.224863, 0.125846),
+            1e-3,
+        );
+        assert_close(
+            rgb_to_oklab((0.0, 0.0, 255.0)),
+            (0.452014, -0.032457, -0.311528),
+            1e-3,
+        );
+    }
+
+    #[test]
+    fn ciede2000_matches_sharma_test_data() {
+        // Pairs from Sharma, Wu and Dalal's CIEDE2000 test data.
+        let pairs = [
+            ((50.0, 2.6772, -79.7751), (50.0, 0.0, -82.7485), 2.0425),
+            ((50.0, 0.0, 0.0), (50.0, -1.0, 2.0), 2.3669),
+            ((50.0, 2.5, 0.0), (73.0, 25.0, -18.0), 27.1492),
+            (
+                (60.2574, -34.0099, 36.2677),
+                (60.4626, -34.1751, 39.4387),
+                1.2644,
+            ),
+            (
+                (22.7233, 20.0904, -46.694),
+                (23.0331, 14.973, -42.5619),
+                2.0373,
+            ),
+        ];
+        for (lab1, lab2, expected) in pairs {
+            let actual = ciede2000(lab1, lab2);
+            assert!(
+                (actual - expected).abs() < 1e-3,
+                "{} != {}",
+                actual,
+                expected
+            );
+            assert!((ciede2000(lab2, lab1) - expected).abs() < 1e-3);
+        }
+    }
+
+    #[test]
+    fn delta_e76_is_euclidean() {
+        assert_eq!(delta_e76((50.0, 0.0, 0.0), (53.0, 4.0, 0.0)), 5.0);
+    }
+
+    #[test]
+    fn redmean_weights_green_most() {
+        assert_eq!(redmean((0.0, 0.0, 0.0), (0.0, 0.0, 0.0)), 0.0);
+        assert!(
+            redmean((0.0, 0.0, 0.0), (0.0, 10.0, 0.0))
+                > redmean((0.0, 0.0, 0.0), (10.0, 0.0, 0.0))
+        );
+    }
+}
@@ -0,0 +1,384 @@
+/// Direct binary search refines an existing halftone by repeatedly toggling
+/// pixels between levels or swapping them with a neighbor, keeping any change
+/// that lowers the error between halftone and target as seen through a
+/// Gaussian model of the human visual system.
+pub struct Dbs {
+    width: isize,
+    height: isize,
+    /// Autocorrelation of the HVS filter, (2 * radius + 1)^2 row-major.
+    autocorrelation: Vec<f32>,
+    radius: isize,
+}
+
+impl Dbs {
+    /// Standard deviation in pixels of the Gaussian HVS filter.
+    const SIGMA: f32 = 1.5;
+
+    pub fn new(width: isize, height: isize) -> Self {
+        let filter_radius = (3.0 * Self::SIGMA).ceil() as isize;
+        let filter_side = 2 * filter_radius + 1;
+        let mut filter =
+            Vec::with_capacity((filter_side * filter_side) as usize);
+        for y in -filter_radius..=filter_radius {
+            for x in -filter_radius..=filter_radius {
+                let d2 = (x * x + y * y) as f32;
+                filter.push((-d2 / (2.0 * Self::SIGMA * Self::SIGMA)).exp());
+            }
+        }
+        let sum: f32 = filter.iter().sum();
+        filter.iter_mut().for_each(|v| *v /= sum);
+
+        let radius = 2 * filter_radius;
+        let side = 2 * radius + 1;
+        let mut autocorrelation = vec![0.0; (side * side) as usize];
+        for dy in -radius..=radius {
+            for dx in -radius..=radius {
+                let mut total = 0.0;
+                for y in -filter_radius..=filter_radius {
+                    for x in -filter_radius..=filter_radius {
+                        let (sx, sy) = (x + dx, y + dy);
+                        if sx.abs() <= filter_radius
+                            && sy.abs() <= filter_radius
+                        {
+                            total += filter[((y + filter_radius) * filter_side
+                                + x
+                                + filter_radius)
+                                as usize]
+                                * filter[((sy + filter_radius) * filter_side
+                                    + sx
+                                    + filter_radius)
+                                    as usize];
+                        }
+                    }
+                }
+                autocorrelation
+                    [((dy + radius) * side + dx + radius) as usize] = total;
+            }
+        }
+
+        Dbs {
+            width,
+            height,
+            autocorrelation,
+            radius,
+        }
+    }
+
+    fn autocorrelation(&self, dx: isize, dy: isize) -> f32 {
+        if dx.abs() > self.radius || dy.abs() > self.radius {
+            return 0.0;
+        }
+        let side = 2 * self.radius + 1;
+        self.autocorrelation
+            [((dy + self.radius) * side + dx + self.radius) as usize]
+    }
+
+    /// Cross-correlation of error (halftone - target) with the autocorrelation,
+    /// interleaved by channel like error.
+    fn cross_correlation(&self, error: &[f32], channels: usize) -> Vec<f32> {
+        let mut correlation = vec![0.0; error.len()];
+        for y in 0..self.height {
+            for x in 0..self.width {
+                let i = (y * self.width + x) as usize * channels;
+                let e = &error[i..i + channels];
+                if e.iter().any(|&e| e != 0.0) {
+                    self.add_correlation(&mut correlation, x, y, e);
+                }
+            }
+        }
+        correlation
+    }
+
+    /// Add the correlation of a change of a at x, y to every pixel around it.
+    fn add_correlation(
+        &self,
+        correlation: &mut [f32],
+        x: isize,
+        y: isize,
+        a: &[f32],
+    ) {
+        let channels = a.len();
+        for ny in
+            (y - self.radius).max(0)..(y + self.radius + 1).min(self.height)
+        {
+            for nx in
+                (x - self.radius).max(0)..(x + self.radius + 1).min(self.width)
+            {
+                let weight = self.autocorrelation(nx - x, ny - y);
+                let i = (ny * self.width + nx) as usize * channels;
+                for (c, a) in a.iter().enumerate() {
+                    correlation[i + c] += a * weight;
+                }
+            }
+        }
+    }
+
+    /// Perceived squared error between halftone and target, both interleaved
+    /// by channel.
+    pub fn perceived_error(
+        &self,
+        target: &[f32],
+        halftone: &[f32],
+        channels: usize,
+    ) -> f32 {
+        let error: Vec<f32> =
+            halftone.iter().zip(target).map(|(h, t)| h - t).collect();
+        let correlation = self.cross_correlation(&error, channels);
+        error.iter().zip(correlation).map(|(e, c)| e * c).sum()
+    }
+
+    /// Refine a halftone of target in place, where both are interleaved by
+    /// channel and every halftone color is one of levels, which holds channels
+    /// values per color, at most MAX_CHANNELS. Stops after max_iterations
+    /// passes over the frame, or once a pass changes at most convergence of all
+    /// pixels. Returns the number of passes made.
+    pub fn refine(
+        &self,
+        target: &[f32],
+        halftone: &mut [f32],
+        levels: &[f32],
+        channels: usize,
+        max_iterations: usize,
+        convergence: f32,
+    ) -> usize {
+        let count = (self.width * self.height) as usize;
+        assert!(target.len() == count * channels);
+        assert!(halftone.len() == count * channels);
+        assert!(!levels.is_empty() && levels.len().is_multiple_of(channels));
+        assert!(channels <= MAX_CHANNELS);
+
+        let levels: Vec<&[f32]> = levels.chunks(channels).collect();
+        let mut level_of: Vec<usize> = halftone
+            .chunks(channels)
+            .map(|h| nearest_level(&levels, h))
+            .collect();
+        for (h, &l) in halftone.chunks_mut(channels).zip(&level_of) {
+            h.copy_from_slice(levels[l]);
+        }
+
+        let error: Vec<f32> =
+            halftone.iter().zip(target).map(|(h, t)| h - t).collect();
+        let mut correlation = self.cross_correlation(&error, channels);
+        let center = self.autocorrelation(0, 0);
+        let mut a = vec![0.0; channels];
+
+        for pass in 0..max_iterations {
+            let mut changes = 0;
+
+            for y in 0..self.height {
+                for x in 0..self.width {
+                    let i = (y * self.width + x) as usize;
+                    let pixel = |values: &[f32], i: usize| {
+                        let mut pixel = [0.0; MAX_CHANNELS];
+                        pixel[..channels].copy_from_slice(
+                            &values[i * channels..(i + 1) * channels],
+                        );
+                        pixel
+                    };
+                    let h_i = pixel(halftone, i);
+                    let c_i = pixel(&correlation, i);
+
+                    // Toggle to another level.
+                    let mut best: Option<Change> = None;
+                    for (level, values) in levels.iter().enumerate() {
+                        if level == level_of[i] {
+                            continue;
+                        }
+                        let delta: f32 = (0..channels)
+                            .map(|c| {
+                                let a = values[c] - h_i[c];
+                                a * a * center + 2.0 * a * c_i[c]
+                            })
+                            .sum();
+                        if best.is_none_or(|(d, _, _)| delta < d) {
+                            best = Some((delta, level, None));
+                        }
+                    }
+
+                    // Swap with a neighbor.
+                    for (dx, dy) in NEIGHBORS {
+                        let (nx, ny) = (x + dx, y + dy);
+                        if nx < 0
+                            || ny < 0
+                            || nx >= self.width
+                            || ny >= self.height
+                        {
+                            continue;
+                        }
+                        let j = (ny * self.width + nx) as usize;
+                        if level_of[j] == level_of[i] {
+                            continue;
+                        }
+                        let neighbor = self.autocorrelation(dx, dy);
+                        let delta: f32 = (0..channels)
+                            .map(|c| {
+                                let a = halftone[j * channels + c] - h_i[c];
+                                2.0 * a * a * (center - neighbor)
+                                    + 2.0
+                                        * a
+                                        * (c_i[c]
+                                            - correlation[j * channels + c])
+                            })
+                            .sum();
+                        if best.is_none_or(|(d, _, _)| delta < d) {
+                            best = Some((
+                                delta,
+                                level_of[j],
+                                Some((j, level_of[i])),
+                            ));
+                        }
+                    }
+
+                    if let Some((delta, level, swap)) = best
+                        && delta < -1e-6
+                    {
+                        for c in 0..channels {
+                            a[c] = levels[level][c] - h_i[c];
+                        }
+                        halftone[i * channels..(i + 1) * channels]
+                            .copy_from_slice(levels[level]);
+                        level_of[i] = level;
+                        self.add_correlation(&mut correlation, x, y, &a);
+
+                        if let Some((j, other_level)) = swap {
+                            let (jx, jy) = (
+                                j as isize % self.width,
+                                j as isize / self.width,
+                            );
+                            halftone[j * channels..(j + 1) * channels]
+                                .copy_from_slice(levels[other_level]);
+                            level_of[j] = other_level;
+                            a.iter_mut().for_each(|a| *a = -*a);
+                            self.add_correlation(&mut correlation, jx, jy, &a);
+                        }
+                        changes += 1;
+                    }
+                }
+            }
+
+            if (changes as f32) <= convergence * count as f32 {
+                return pass + 1;
+            }
+        }
+
+        max_iterations
+    }
+}
+
+/// Most channels of the colors refined, enough for RGB.
+pub const MAX_CHANNELS: usize = 3;
+
+/// Change in perceived error, the pixel's new level, and the neighbor it swaps
+/// with along with that neighbor's new level.
+type Change = (f32, usize, Option<(usize, usize)>);
+
+const NEIGHBORS: [(isize, isize); 8] = [
+    (-1, -1),
+    (0, -1),
+    (1, -1),
+    (-1, 0),
+    (1, 0),
+    (-1, 1),
+    (0, 1),
+    (1, 1),
+];
+
+fn nearest_level(levels: &[&[f32]], value: &[f32]) -> usize {
+    let distance = |level: &[f32]| -> f32 {
+        level.iter().zip(value).map(|(l, v)| (l - v).powi(2)).sum()
+    };
+    (0..levels.len())
+        .min_by(|&a, &b| distance(levels[a]).total_cmp(&distance(levels[b])))
+        .unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SIZE: isize = 24;
+
+    fn gradient() -> Vec<f32> {
+        (0..SIZE * SIZE)
+            .map(|i| (i % SIZE) as f32 / (SIZE - 1) as f32 * 255.0)
+            .collect()
+    }
+
+    fn threshold(target: &[f32]) -> Vec<f32> {
+        target
+            .iter()
+            .map(|&t| if t < 127.5 { 0.0 } else { 255.0 })
+            .collect()
+    }
+
+    #[test]
+    fn refine_lowers_perceived_error() {
+        let dbs = Dbs::new(SIZE, SIZE);
+        let target = gradient();
+        let mut halftone = threshold(&target);
+        let before = dbs.perceived_error(&target, &halftone, 1);
+
+        dbs.refine(&target, &mut halftone, &[0.0, 255.0], 1, 10, 0.0);
+        let after = dbs.perceived_error(&target, &halftone, 1);
+
+        assert!(after < before * 0.5, "{} -> {}", before, after);
+        assert!(halftone.iter().all(|&h| h == 0.0 || h == 255.0));
+    }
+
+    #[test]
+    fn refine_keeps_average_density() {
+        let dbs = Dbs::new(SIZE, SIZE);
+        let target = vec![64.0; (SIZE * SIZE) as usize];
+        let mut halftone = vec![0.0; (SIZE * SIZE) as usize];
+
+        dbs.refine(&target, &mut halftone, &[0.0, 255.0], 1, 20, 0.0);
+        let mean = halftone.iter().sum::<f32>() / halftone.len() as f32;
+
+        assert!((mean - 64.0).abs() < 255.0 * 0.02, "averaged to {}", mean);
+    }
+
+    #[test]
+    fn refine_stops_once_converged() {
+        let dbs = Dbs::new(SIZE, SIZE);
+        let target = gradient();
+        let mut halftone = threshold(&target);
+
+        let passes =
+            dbs.refine(&target, &mut halftone, &[0.0, 255.0], 1, 100, 0.0);
+        assert!(passes < 100);
+
+        let mut refined = halftone.clone();
+        assert_eq!(
+            dbs.refine(&target, &mut refined, &[0.0, 255.0], 1, 100, 0.0),
+            1
+        );
+        assert_eq!(halftone, refined);
+    }
+
+    #[test]
+    fn refine_snaps_to_levels() {
+        let dbs = Dbs::new(SIZE, SIZE);
+        let target = gradient();
+        let mut halftone = target.clone();
+        let levels = [0.0, 85.0, 170.0, 255.0];
+
+        dbs.refine(&target, &mut halftone, &levels, 1, 5, 0.0);
+        assert!(halftone.iter().all(|h| levels.contains(h)));
+    }
+
+    #[test]
+    fn refine_mixes_colors_of_a_palette() {
+        let dbs = Dbs::new(SIZE, SIZE);
+        let purple = [128.0, 0.0, 128.0];
+        let target: Vec<f32> = purple.repeat((SIZE * SIZE) as usize);
+        let mut halftone = vec![0.0; target.len()];
+        let levels = [0.0, 0.0, 0.0, 255.0, 0.0, 0.0, 0.0, 0.0, 255.0];
+
+        dbs.refine(&target, &mut halftone, &levels, 3, 20, 0.0);
+        for (c, expected) in purple.into_iter().enumerate() {
+            let mean = halftone.iter().skip(c).step_by(3).sum::<f32>()
+                / (SIZE * SIZE) as f32;
+            assert!((mean - expected).abs() < 255.0 * 0.03, "{}", mean);
+        }
+    }
+}
@@ -1,7 +1,25 @@
+use std::fs;
 use std::io::{Read, Write};
+use std::thread;
 
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+use crate::color::{decode_srgb, encode_srgb, luma};
+use crate::dbs::Dbs;
+use crate::dot_diffusion::{self, ClassMatrix};
 use crate::ffmpeg;
-use crate::frame::{Frame, Resolution};
+use crate::frame::{FloatFrame, Frame, Resolution, RgbPixel};
+use crate::halftone::{self, Halftone};
+use crate::kernel::{DiffusionKernel, VariableKernel};
+use crate::levels::ChannelLevels;
+use crate::palette::Palette;
+use crate::palette_gen::ScenePalettes;
+use crate::pattern::{self, PatternMethod, PlanCache};
+use crate::riemersma::{self, ErrorQueue};
+use crate::scene::{self, SceneDetector, SceneOpts};
+use crate::temporal::{TemporalOpts, TemporalState};
+use crate::threshold::ThresholdMatrix;
 
 #[derive(Debug)]
 pub struct DitherOpts {
@@ -10,12 +28,298 @@ pub struct DitherOpts {
     pub input_path: String,
     pub output_path: String,
     pub algo: DitherAlgoOpts,
+    /// Dither in linear light rather than on the gamma encoded sRGB values.
+    pub linear: bool,
+    /// Keep the output of static regions from frame to frame.
+    pub temporal: Option<TemporalOpts>,
+    /// Detect scene cuts and dither each scene from scratch.
+    pub scenes: Option<SceneOpts>,
+    /// Palettes that replace the palette of algo from their scene on.
+    pub scene_palettes: Option<ScenePalettes>,
 }
 
 #[derive(Debug, Clone)]
 pub enum DitherAlgoOpts {
-    Atkinson { palette_count: u32 },
-    FsColor { palette_count: u32 },
+    Atkinson {
+        quantizer: Quantizer,
+        scan: ScanOrder,
+    },
+    FsColor {
+        quantizer: Quantizer,
+        scan: ScanOrder,
+    },
+    ErrorDiffusion {
+        kernel: DiffusionKernel,
+        quantizer: Quantizer,
+        scan: ScanOrder,
+    },
+    VariableDiffusion {
+        kernel: VariableKernel,
+        quantizer: Quantizer,
+        scan: ScanOrder,
+    },
+    DotDiffusion {
+        classes: ClassMatrix,
+        quantizer: Quantizer,
+    },
+    Ordered {
+        matrix: ThresholdMatrix,
+        quantizer: Quantizer,
+    },
+    Pattern {
+        method: PatternMethod,
+        palette: Palette,
+        matrix: ThresholdMatrix,
+        plans: PlanCache,
+    },
+    Halftone {
+        screens: Halftone,
+        /// Palette the inked colors are mapped to.
+        palette: Option<Palette>,
+    },
+    Dbs {
+        initial: Box<DitherAlgoOpts>,
+        max_iterations: usize,
+        convergence: f32,
+        quantizer: Quantizer,
+    },
+    Random {
+        distribution: NoiseDistribution,
+        seed: u64,
+        /// Draw new noise every frame instead of reusing the same noise.
+        reseed_per_frame: bool,
+        quantizer: Quantizer,
+    },
+    Riemersma {
+        queue_length: usize,
+        ratio: f32,
+        quantizer: Quantizer,
+    },
+}
+
+impl DitherAlgoOpts {
+    /// The same algorithm with its levels and palettes in linear light, to
+    /// dither frames decoded to linear light with.
+    pub fn linearized(&self) -> DitherAlgoOpts {
+        let mut algo = self.clone();
+        match &mut algo {
+            DitherAlgoOpts::Atkinson { quantizer, .. }
+            | DitherAlgoOpts::FsColor { quantizer, .. }
+            | DitherAlgoOpts::ErrorDiffusion { quantizer, .. }
+            | DitherAlgoOpts::VariableDiffusion { quantizer, .. }
+            | DitherAlgoOpts::DotDiffusion { quantizer, .. }
+            | DitherAlgoOpts::Ordered { quantizer, .. }
+            | DitherAlgoOpts::Random { quantizer, .. }
+            | DitherAlgoOpts::Riemersma { quantizer, .. } => {
+                *quantizer = quantizer.linearized();
+            }
+            DitherAlgoOpts::Pattern { palette, .. } => {
+                *palette = palette.linearized();
+            }
+            DitherAlgoOpts::Halftone { palette, .. } => {
+                *palette = palette.as_ref().map(Palette::linearized);
+            }
+            DitherAlgoOpts::Dbs {
+                initial, quantizer, ..
+            } => {
+                **initial = initial.linearized();
+                *quantizer = quantizer.linearized();
+            }
+        }
+        algo
+    }
+
+    /// The same algorithm, dithering to palette wherever it dithers to a
+    /// palette.
+    pub fn with_palette(&self, palette: &Palette) -> DitherAlgoOpts {
+        let mut algo = self.clone();
+        match &mut algo {
+            DitherAlgoOpts::Atkinson { quantizer, .. }
+            | DitherAlgoOpts::FsColor { quantizer, .. }
+            | DitherAlgoOpts::ErrorDiffusion { quantizer, .. }
+            | DitherAlgoOpts::VariableDiffusion { quantizer, .. }
+            | DitherAlgoOpts::DotDiffusion { quantizer, .. }
+            | DitherAlgoOpts::Ordered { quantizer, .. }
+            | DitherAlgoOpts::Random { quantizer, .. }
+            | DitherAlgoOpts::Riemersma { quantizer, .. } => {
+                *quantizer = quantizer.with_palette(palette);
+            }
+            DitherAlgoOpts::Pattern {
+                palette: old,
+                plans,
+                ..
+            } => {
+                *old = palette.clone();
+                // Plans of the old palette don't mix colors of the new one.
+                *plans = PlanCache::default();
+            }
+            DitherAlgoOpts::Halftone {
+                palette: Some(old), ..
+            } => {
+                *old = palette.clone();
+            }
+            DitherAlgoOpts::Halftone { palette: None, .. } => {}
+            DitherAlgoOpts::Dbs {
+                initial, quantizer, ..
+            } => {
+                **initial = initial.with_palette(palette);
+                *quantizer = quantizer.with_palette(palette);
+            }
+        }
+        algo
+    }
+}
+
+/// Colors a frame is reduced to.
+#[derive(Debug, Clone)]
+pub enum Quantizer {
+    /// palette_count evenly spaced levels of luma, or of every channel.
+    Uniform { palette_count: u32, mode: ColorMode },
+    /// Arbitrary levels of every channel. In gray mode luma is quantized to
+    /// the levels of red, which should be [ChannelLevels::shared] so every
+    /// channel can output them.
+    Levels {
+        levels: ChannelLevels,
+        mode: ColorMode,
+    },
+    /// The nearest color of an arbitrary palette.
+    Palette(Palette),
+}
+
+impl Quantizer {
+    pub fn quantize(&self, rgb: RgbPixel) -> RgbPixel {
+        match self {
+            Quantizer::Uniform {
+                palette_count,
+                mode,
+            } => quantize_pixel(rgb, quantize_gap(*palette_count), *mode),
+            Quantizer::Levels { levels, mode } => match mode {
+                ColorMode::Gray => {
+                    let luma = levels.nearest(0, luma(rgb));
+                    (luma, luma, luma)
+                }
+                ColorMode::Rgb => levels.quantize(rgb),
+            },
+            Quantizer::Palette(palette) => palette.get(palette.nearest(rgb)),
+        }
+    }
+
+    /// A function quantizing a pixel against a threshold per channel. Every
+    /// channel rounds up from the level below it to the one above wherever
+    /// its fraction of the way between them exceeds its threshold, so
+    /// thresholds uniform in [0, 1) keep the mean tone however the levels are
+    /// spaced. In gray mode luma is compared to the luma of the thresholds.
+    /// Palettes offset the pixel by threshold - 0.5 of their step instead.
+    pub fn thresholder(&self) -> impl Fn(RgbPixel, RgbPixel) -> RgbPixel + '_ {
+        let levels = self.channel_levels();
+        let step = match self {
+            Quantizer::Palette(palette) => palette.step(),
+            _ => 0.0,
+        };
+
+        move |rgb, thresholds| {
+            let ((r, g, b), (tr, tg, tb)) = (rgb, thresholds);
+            match &levels {
+                Some((levels, ColorMode::Gray)) => {
+                    let luma = levels.threshold(0, luma(rgb), luma(thresholds));
+                    (luma, luma, luma)
+                }
+                Some((levels, ColorMode::Rgb)) => (
+                    levels.threshold(0, r, tr),
+                    levels.threshold(1, g, tg),
+                    levels.threshold(2, b, tb),
+                ),
+                None => self.quantize((
+                    r + (tr - 0.5) * step,
+                    g + (tg - 0.5) * step,
+                    b + (tb - 0.5) * step,
+                )),
+            }
+        }
+    }
+
+    /// A function giving the fractional index of a value of a channel among
+    /// the levels it's quantized to, see [ChannelLevels::position]. For
+    /// palettes this is the value in palette steps.
+    pub fn level_position(&self) -> impl Fn(usize, f32) -> f32 {
+        let levels = self.channel_levels();
+        let step = match self {
+            Quantizer::Palette(palette) => palette.step(),
+            _ => 0.0,
+        };
+
+        move |channel, value| match &levels {
+            Some((levels, ColorMode::Gray)) => levels.position(0, value),
+            Some((levels, ColorMode::Rgb)) => levels.position(channel, value),
+            None => value / step,
+        }
+    }
+
+    /// The same quantizer with its levels or palette in linear light.
+    pub fn linearized(&self) -> Quantizer {
+        match self {
+            Quantizer::Uniform { mode, .. }
+            | Quantizer::Levels { mode, .. } => {
+                let (levels, _) = self.channel_levels().unwrap();
+                Quantizer::Levels {
+                    levels: levels.linearized(),
+                    mode: *mode,
+                }
+            }
+            Quantizer::Palette(palette) => {
+                Quantizer::Palette(palette.linearized())
+            }
+        }
+    }
+
+    /// palette if this quantizes to a palette, or else the same levels.
+    pub fn with_palette(&self, palette: &Palette) -> Quantizer {
+        match self {
+            Quantizer::Palette(_) => Quantizer::Palette(palette.clone()),
+            _ => self.clone(),
+        }
+    }
+
+    /// Output levels of every channel and whether they apply to luma or to
+    /// every channel, or None for palettes.
+    fn channel_levels(&self) -> Option<(ChannelLevels, ColorMode)> {
+        match self {
+            Quantizer::Uniform {
+                palette_count,
+                mode,
+            } => Some((ChannelLevels::uniform(*palette_count), *mode)),
+            Quantizer::Levels { levels, mode } => Some((levels.clone(), *mode)),
+            Quantizer::Palette(_) => None,
+        }
+    }
+}
+
+/// Order in which error diffusion visits pixels.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ScanOrder {
+    /// Every row left to right.
+    Raster,
+    /// Alternate direction every row, mirroring the kernel on right to left
+    /// rows. This breaks up the diagonal "worm" artifacts of raster scanning.
+    Serpentine,
+}
+
+/// Probability distribution of the noise random dithering thresholds against.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum NoiseDistribution {
+    /// Uniform in [-0.5, 0.5) quantization steps.
+    Uniform,
+    /// Triangular in (-1, 1) quantization steps, the sum of two uniform
+    /// variables. Its error doesn't depend on the input's level.
+    Triangular,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ColorMode {
+    /// Quantize luma, producing grayscale output.
+    Gray,
+    /// Quantize each RGB channel independently.
+    Rgb,
 }
 
 pub fn dither_video(opts: DitherOpts) -> Result<(), String> {
@@ -54,25 +358,63 @@ pub fn dither_video(opts: DitherOpts) -> Result<(), String> {
         .take()
         .expect("Expected stdin to be present");
 
-    loop {
-        if let Ok(_) = frame_reader.read_exact(&mut frame_buf) {
-            match opts.algo {
-                DitherAlgoOpts::Atkinson { palette_count } => {
-                    dither_frame_atkinson(
-                        dither_res_w,
-                        dither_res_h,
-                        &mut frame_buf,
-                        palette_count,
-                    );
-                }
-                DitherAlgoOpts::FsColor { palette_count } => {
-                    dither_frame_floyd_steinberg_color(
-                        dither_res_w,
-                        dither_res_h,
-                        &mut frame_buf,
-                        palette_count,
-                    );
-                }
+    let mut algo = opts.algo.clone();
+    let mut linearized = opts.linear.then(|| algo.linearized());
+    let mut temporal = opts.temporal.map(|temporal| {
+        TemporalState::new(
+            temporal,
+            dither_res_w as usize,
+            dither_res_h as usize,
+        )
+    });
+    let mut scene_detector = opts
+        .scenes
+        .as_ref()
+        .map(|scenes| SceneDetector::new(scenes.threshold));
+    let mut cuts = Vec::new();
+
+    for frame_index in 0.. {
+        if frame_reader.read_exact(&mut frame_buf).is_ok() {
+            let cut = scene_detector
+                .as_mut()
+                .and_then(|detector| detector.detect(frame_index, &frame_buf));
+            let palette = opts
+                .scene_palettes
+                .as_ref()
+                .and_then(|palettes| palettes.palette_at(frame_index));
+            if let Some(palette) = &palette {
+                algo = opts.algo.with_palette(palette);
+                linearized = opts.linear.then(|| algo.linearized());
+            }
+            // The previous output can't be kept across cuts nor in colors of
+            // another palette.
+            if (cut.is_some() || palette.is_some())
+                && let Some(temporal) = &mut temporal
+            {
+                temporal.reset();
+            }
+            cuts.extend(cut);
+            if let Some(temporal) = &mut temporal {
+                temporal.prepare(&mut frame_buf);
+            }
+            match &linearized {
+                Some(linearized) => dither_frame_linear(
+                    dither_res_w,
+                    dither_res_h,
+                    &mut frame_buf,
+                    linearized,
+                    frame_index,
+                ),
+                None => dither_frame(
+                    dither_res_w,
+                    dither_res_h,
+                    &mut frame_buf,
+                    &algo,
+                    frame_index,
+                ),
+            }
+            if let Some(temporal) = &mut temporal {
+                temporal.stabilize(&mut frame_buf);
             }
 
             frame_writer
@@ -86,63 +428,554 @@ pub fn dither_video(opts: DitherOpts) -> Result<(), String> {
         }
     }
 
+    if let Some(SceneOpts {
+        threshold,
+        report_path: Some(report_path),
+    }) = &opts.scenes
+    {
+        let report = scene::report_json(&cuts, *threshold, input_frame_rate);
+        fs::write(report_path, report)
+            .map_err(|e| format!("Writing scene report failed: {}", e))?;
+    }
+
     Ok(())
 }
 
-pub fn dither_frame_atkinson(
+/// Dither a single frame, where frame_index is its position in the video for
+/// algorithms that vary over time.
+pub fn dither_frame(
     width: isize,
     height: isize,
     buffer: &mut [u8],
-    palette_count: u32,
+    algo: &DitherAlgoOpts,
+    frame_index: u64,
 ) {
-    let mut frame = Frame::new(width, height, buffer);
+    let mut output = Frame::new(width, height, buffer);
+    let mut frame = FloatFrame::from_frame(&output);
+
+    dither_float_frame(&mut frame, algo, frame_index);
+    frame.write_to(&mut output);
+}
 
-    const PIXEL_OFFSETS: [(isize, isize); 6] =
-        [(1, 0), (2, 0), (-1, 1), (0, 1), (1, 1), (0, 2)];
-    let gap = quantize_gap(palette_count);
+/// Dither a frame of unrounded values, see [dither_frame].
+pub fn dither_float_frame(
+    frame: &mut FloatFrame,
+    algo: &DitherAlgoOpts,
+    frame_index: u64,
+) {
+    match algo {
+        DitherAlgoOpts::Atkinson { quantizer, scan } => {
+            dither_frame_atkinson(frame, quantizer, *scan);
+        }
+        DitherAlgoOpts::FsColor { quantizer, scan } => {
+            dither_frame_floyd_steinberg_color(frame, quantizer, *scan);
+        }
+        DitherAlgoOpts::ErrorDiffusion {
+            kernel,
+            quantizer,
+            scan,
+        } => {
+            dither_frame_error_diffusion(frame, kernel, quantizer, *scan);
+        }
+        DitherAlgoOpts::VariableDiffusion {
+            kernel,
+            quantizer,
+            scan,
+        } => {
+            dither_frame_variable_diffusion(frame, *kernel, quantizer, *scan);
+        }
+        DitherAlgoOpts::DotDiffusion { classes, quantizer } => {
+            dither_frame_dot_diffusion(frame, classes, quantizer);
+        }
+        DitherAlgoOpts::Ordered { matrix, quantizer } => {
+            dither_frame_ordered(frame, matrix, quantizer);
+        }
+        DitherAlgoOpts::Pattern {
+            method,
+            palette,
+            matrix,
+            plans,
+        } => {
+            dither_frame_pattern(frame, *method, palette, matrix, plans);
+        }
+        DitherAlgoOpts::Halftone { screens, palette } => {
+            dither_frame_halftone(frame, screens, palette.as_ref());
+        }
+        DitherAlgoOpts::Dbs {
+            initial,
+            max_iterations,
+            convergence,
+            quantizer,
+        } => {
+            dither_frame_dbs(
+                frame,
+                initial,
+                *max_iterations,
+                *convergence,
+                quantizer,
+            );
+        }
+        DitherAlgoOpts::Random {
+            distribution,
+            seed,
+            reseed_per_frame,
+            quantizer,
+        } => {
+            let seed = if *reseed_per_frame {
+                seed.wrapping_add(frame_index)
+            } else {
+                *seed
+            };
+            dither_frame_random(frame, *distribution, seed, quantizer);
+        }
+        DitherAlgoOpts::Riemersma {
+            queue_length,
+            ratio,
+            quantizer,
+        } => {
+            dither_frame_riemersma(frame, *queue_length, *ratio, quantizer);
+        }
+    }
+}
 
+/// Dither a single frame in linear light with linearized, the result of
+/// algo.linearized(). The frame is decoded to unrounded linear values, so
+/// shadows keep their precision, and the output encoded back to the sRGB
+/// colors of algo.
+pub fn dither_frame_linear(
+    width: isize,
+    height: isize,
+    buffer: &mut [u8],
+    linearized: &DitherAlgoOpts,
+    frame_index: u64,
+) {
+    let mut output = Frame::new(width, height, buffer);
+    let mut frame = FloatFrame::from_frame(&output);
+    let decode: Vec<f32> = (0..=255)
+        .map(|v| decode_srgb((v as f32, 0.0, 0.0)).0)
+        .collect();
     for y in 0..frame.height {
         for x in 0..frame.width {
-            let pixel = frame.get_gray(x, y).unwrap();
-            let quantized = quantize(pixel, gap);
-            let error = pixel - quantized;
-            let eight_error = error / 8.0;
+            let (r, g, b) = frame.get_rgb(x, y).unwrap();
+            let rgb =
+                (decode[r as usize], decode[g as usize], decode[b as usize]);
+            frame.set_rgb(x, y, rgb);
+        }
+    }
 
-            frame.set_gray(x, y, quantized);
+    dither_float_frame(&mut frame, linearized, frame_index);
 
-            for (ox, oy) in PIXEL_OFFSETS {
-                let nx = x + ox;
-                let ny = y + oy;
-                if let Some(p) = frame.get_gray(nx, ny) {
-                    frame.set_gray(nx, ny, p + eight_error);
+    for y in 0..frame.height {
+        for x in 0..frame.width {
+            let rgb = encode_srgb(frame.get_rgb(x, y).unwrap());
+            frame.set_rgb(x, y, rgb);
+        }
+    }
+    frame.write_to(&mut output);
+}
+
+pub fn dither_frame_atkinson(
+    frame: &mut FloatFrame,
+    quantizer: &Quantizer,
+    scan: ScanOrder,
+) {
+    diffuse_error(frame, &DiffusionKernel::atkinson(), scan, |rgb| {
+        quantizer.quantize(rgb)
+    });
+}
+
+pub fn dither_frame_floyd_steinberg_color(
+    frame: &mut FloatFrame,
+    quantizer: &Quantizer,
+    scan: ScanOrder,
+) {
+    dither_frame_error_diffusion(
+        frame,
+        &DiffusionKernel::floyd_steinberg(),
+        quantizer,
+        scan,
+    );
+}
+
+/// Apply error diffusion dithering with an arbitrary kernel.
+pub fn dither_frame_error_diffusion(
+    frame: &mut FloatFrame,
+    kernel: &DiffusionKernel,
+    quantizer: &Quantizer,
+    scan: ScanOrder,
+) {
+    diffuse_error(frame, kernel, scan, |rgb| quantizer.quantize(rgb));
+}
+
+/// Apply error diffusion whose coefficients, and with Zhou-Fang the
+/// quantization threshold, vary with each channel's intensity. Intensity is
+/// taken relative to the levels around it.
+pub fn dither_frame_variable_diffusion(
+    frame: &mut FloatFrame,
+    kernel: VariableKernel,
+    quantizer: &Quantizer,
+    scan: ScanOrder,
+) {
+    let input = frame.clone();
+    let table = kernel.table();
+    let threshold = quantizer.thresholder();
+    let position = quantizer.level_position();
+    // Fixed seed so modulation is the same on every frame.
+    let mut rng = StdRng::seed_from_u64(0);
+
+    for y in 0..frame.height {
+        let reverse = scan == ScanOrder::Serpentine && y % 2 == 1;
+        let dx = if reverse { -1 } else { 1 };
+
+        for i in 0..frame.width {
+            let x = if reverse { frame.width - 1 - i } else { i };
+            let (r, g, b) = input.get_rgb(x, y).unwrap();
+            let (acc_r, acc_g, acc_b) = frame.get_rgb(x, y).unwrap();
+
+            let (inputs, values) = ([r, g, b], [acc_r, acc_g, acc_b]);
+            let coefs: [_; 3] = std::array::from_fn(|c| {
+                let intensity = position(c, inputs[c]).fract();
+                table[(intensity * 255.0).round() as usize]
+            });
+            let thresholds: [f32; 3] = std::array::from_fn(|c| {
+                0.5 + (rng.random::<f32>() - 0.5) * coefs[c].modulation
+            });
+            let quantized = threshold(
+                (acc_r, acc_g, acc_b),
+                (thresholds[0], thresholds[1], thresholds[2]),
+            );
+            frame.set_rgb(x, y, quantized);
+
+            let quantized = [quantized.0, quantized.1, quantized.2];
+            let errors: [(f32, f32, f32); 3] = std::array::from_fn(|c| {
+                let error = values[c] - quantized[c];
+                (
+                    error * coefs[c].right,
+                    error * coefs[c].below_left,
+                    error * coefs[c].below,
+                )
+            });
+
+            let (right, below_left, below) = (
+                errors.map(|e| e.0),
+                errors.map(|e| e.1),
+                errors.map(|e| e.2),
+            );
+            for ((ox, oy), error) in
+                [((dx, 0), right), ((-dx, 1), below_left), ((0, 1), below)]
+            {
+                if let Some((r, g, b)) = frame.get_rgb(x + ox, y + oy) {
+                    frame.set_rgb(
+                        x + ox,
+                        y + oy,
+                        (r + error[0], g + error[1], b + error[2]),
+                    );
                 }
             }
         }
     }
 }
 
-pub fn dither_frame_floyd_steinberg_color(
-    width: isize,
-    height: isize,
-    buffer: &mut [u8],
-    palette_count: u32,
+/// Apply Knuth's dot diffusion. Pixels are quantized one class at a time and
+/// only diffuse error to neighbors of a higher class, so every pixel of a class
+/// can be processed in parallel.
+pub fn dither_frame_dot_diffusion(
+    frame: &mut FloatFrame,
+    classes: &ClassMatrix,
+    quantizer: &Quantizer,
 ) {
-    let mut frame = Frame::new(width, height, buffer);
+    let (width, height) = (frame.width, frame.height);
+    let threads = thread::available_parallelism().map_or(1, |n| n.get());
 
-    const OFFSET_COEF: [((isize, isize), f32); 4] = [
-        ((1, 0), 7.0 / 16.0),
-        ((-1, 1), 3.0 / 16.0),
-        ((0, 1), 5.0 / 16.0),
-        ((1, 1), 1.0 / 16.0),
-    ];
-    let gap = quantize_gap(palette_count);
+    let mut pixels: Vec<RgbPixel> = (0..height)
+        .flat_map(|y| (0..width).map(move |x| (x, y)))
+        .map(|(x, y)| frame.get_rgb(x, y).unwrap())
+        .collect();
+    dot_diffusion::diffuse(
+        &mut pixels,
+        width,
+        height,
+        classes,
+        threads,
+        |rgb| quantizer.quantize(rgb),
+    );
+
+    for (i, &rgb) in pixels.iter().enumerate() {
+        let i = i as isize;
+        frame.set_rgb(i % width, i / width, rgb);
+    }
+}
+
+/// Apply ordered dithering, quantizing every pixel against its threshold in the
+/// tiled matrix. Unlike error diffusion, a pixel's output only depends on its
+/// own value, so static regions stay stable across frames.
+pub fn dither_frame_ordered(
+    frame: &mut FloatFrame,
+    matrix: &ThresholdMatrix,
+    quantizer: &Quantizer,
+) {
+    let threshold = quantizer.thresholder();
+
+    for y in 0..frame.height {
+        for x in 0..frame.width {
+            let t = matrix.get(x, y);
+            let quantized = threshold(frame.get_rgb(x, y).unwrap(), (t, t, t));
+
+            frame.set_rgb(x, y, quantized);
+        }
+    }
+}
+
+/// Ordered dithering against an arbitrary palette, where the matrix picks one
+/// color of every pixel's mixing plan. Plans have one entry per matrix cell, up
+/// to 64, and are computed once per distinct color, reusing those in plans
+/// from earlier frames.
+pub fn dither_frame_pattern(
+    frame: &mut FloatFrame,
+    method: PatternMethod,
+    palette: &Palette,
+    matrix: &ThresholdMatrix,
+    plans: &PlanCache,
+) {
+    let plan_size = (matrix.width * matrix.height).min(64);
+    let mut plans = plans.plans();
 
     for y in 0..frame.height {
         for x in 0..frame.width {
             let (r, g, b) = frame.get_rgb(x, y).unwrap();
-            let quantized_r = quantize(r, gap);
-            let quantized_g = quantize(g, gap);
-            let quantized_b = quantize(b, gap);
+            let plan = plans
+                .entry((r.to_bits(), g.to_bits(), b.to_bits()))
+                .or_insert_with(|| {
+                    pattern::mixing_plan(method, palette, (r, g, b), plan_size)
+                });
+            let index = (matrix.get(x, y) * plan_size as f32) as usize;
+            frame.set_rgb(x, y, palette.get(plan[index.min(plan_size - 1)]));
+        }
+    }
+}
+
+/// Apply clustered-dot halftoning. In CMYK, each separation is screened on its
+/// own and the inked separations are composited back to RGB. With a palette,
+/// the composited color is mapped to its nearest palette color.
+pub fn dither_frame_halftone(
+    frame: &mut FloatFrame,
+    screens: &Halftone,
+    palette: Option<&Palette>,
+) {
+    for y in 0..frame.height {
+        for x in 0..frame.width {
+            let rgb = frame.get_rgb(x, y).unwrap();
+            let output = match screens {
+                Halftone::Gray(screen) => {
+                    if screen.is_inked(x, y, 1.0 - luma(rgb) / 255.0) {
+                        (0.0, 0.0, 0.0)
+                    } else {
+                        (255.0, 255.0, 255.0)
+                    }
+                }
+                Halftone::Cmyk {
+                    cyan,
+                    magenta,
+                    yellow,
+                    black,
+                } => {
+                    let (c, m, y_, k) = halftone::rgb_to_cmyk(rgb);
+                    let ink = |screen: &halftone::Screen, coverage| {
+                        if screen.is_inked(x, y, coverage) {
+                            1.0
+                        } else {
+                            0.0
+                        }
+                    };
+                    let k = ink(black, k);
+
+                    (
+                        255.0 * (1.0 - ink(cyan, c)) * (1.0 - k),
+                        255.0 * (1.0 - ink(magenta, m)) * (1.0 - k),
+                        255.0 * (1.0 - ink(yellow, y_)) * (1.0 - k),
+                    )
+                }
+            };
+            let output = match palette {
+                Some(palette) => palette.get(palette.nearest(output)),
+                None => output,
+            };
+
+            frame.set_rgb(x, y, output);
+        }
+    }
+}
+
+/// Threshold every pixel against seeded white noise. The same seed always gives
+/// the same noise.
+pub fn dither_frame_random(
+    frame: &mut FloatFrame,
+    distribution: NoiseDistribution,
+    seed: u64,
+    quantizer: &Quantizer,
+) {
+    let mut rng = StdRng::seed_from_u64(seed);
+    let threshold = quantizer.thresholder();
+
+    for y in 0..frame.height {
+        for x in 0..frame.width {
+            let noise = match distribution {
+                NoiseDistribution::Uniform => rng.random::<f32>() - 0.5,
+                NoiseDistribution::Triangular => {
+                    rng.random::<f32>() + rng.random::<f32>() - 1.0
+                }
+            };
+            let t = 0.5 + noise;
+            let quantized = threshold(frame.get_rgb(x, y).unwrap(), (t, t, t));
+
+            frame.set_rgb(x, y, quantized);
+        }
+    }
+}
+
+/// Apply Riemersma dithering, which quantizes pixels along a Hilbert curve and
+/// offsets each by the exponentially decaying sum of the last queue_length
+/// errors along it. ratio is the weight of the oldest error relative to the
+/// newest.
+pub fn dither_frame_riemersma(
+    frame: &mut FloatFrame,
+    queue_length: usize,
+    ratio: f32,
+    quantizer: &Quantizer,
+) {
+    let mut queue = ErrorQueue::new(queue_length, ratio);
+
+    for (x, y) in riemersma::hilbert_curve(frame.width, frame.height) {
+        let (r, g, b) = frame.get_rgb(x, y).unwrap();
+        let (err_r, err_g, err_b) = queue.weighted_error();
+        let (quantized_r, quantized_g, quantized_b) =
+            quantizer.quantize((r + err_r, g + err_g, b + err_b));
+
+        frame.set_rgb(x, y, (quantized_r, quantized_g, quantized_b));
+        queue.push((r - quantized_r, g - quantized_g, b - quantized_b));
+    }
+}
+
+/// Dither with initial, then refine its output with direct binary search
+/// towards the original frame. Slow, meant for stills and poster frames.
+pub fn dither_frame_dbs(
+    frame: &mut FloatFrame,
+    initial: &DitherAlgoOpts,
+    max_iterations: usize,
+    convergence: f32,
+    quantizer: &Quantizer,
+) {
+    let (width, height) = (frame.width, frame.height);
+    let target_frame = frame.clone();
+    dither_float_frame(frame, initial, 0);
+
+    let dbs = Dbs::new(width, height);
+    let coordinates: Vec<(isize, isize)> = (0..height)
+        .flat_map(|y| (0..width).map(move |x| (x, y)))
+        .collect();
+    let pixels = |frame: &FloatFrame| -> Vec<RgbPixel> {
+        coordinates
+            .iter()
+            .map(|&(x, y)| frame.get_rgb(x, y).unwrap())
+            .collect()
+    };
+    let (target, initial) = (pixels(&target_frame), pixels(frame));
+
+    let refined: Vec<RgbPixel> = match quantizer {
+        // Levels are independent per channel, so refine each on its own. Luma
+        // is refined against red's levels and copied to every channel.
+        Quantizer::Uniform { .. } | Quantizer::Levels { .. } => {
+            let (levels, mode) = quantizer.channel_levels().unwrap();
+            // Value of a pixel to refine and the channel whose levels to use.
+            type Channel = (fn(RgbPixel) -> f32, usize);
+            let channels: Vec<Channel> = match mode {
+                ColorMode::Gray => vec![(luma, 0)],
+                ColorMode::Rgb => vec![
+                    (|(r, _, _)| r, 0),
+                    (|(_, g, _)| g, 1),
+                    (|(_, _, b)| b, 2),
+                ],
+            };
+
+            let planes: Vec<Vec<f32>> = channels
+                .into_iter()
+                .map(|(channel, index)| {
+                    let target: Vec<f32> =
+                        target.iter().map(|&p| channel(p)).collect();
+                    let mut halftone: Vec<f32> =
+                        initial.iter().map(|&p| channel(p)).collect();
+                    dbs.refine(
+                        &target,
+                        &mut halftone,
+                        levels.channel(index),
+                        1,
+                        max_iterations,
+                        convergence,
+                    );
+                    halftone
+                })
+                .collect();
+
+            (0..coordinates.len())
+                .map(|i| match mode {
+                    ColorMode::Gray => {
+                        (planes[0][i], planes[0][i], planes[0][i])
+                    }
+                    ColorMode::Rgb => {
+                        (planes[0][i], planes[1][i], planes[2][i])
+                    }
+                })
+                .collect()
+        }
+        Quantizer::Palette(palette) => {
+            let interleave = |pixels: &[RgbPixel]| -> Vec<f32> {
+                pixels.iter().flat_map(|&(r, g, b)| [r, g, b]).collect()
+            };
+            let mut halftone = interleave(&initial);
+            dbs.refine(
+                &interleave(&target),
+                &mut halftone,
+                &interleave(palette.colors()),
+                3,
+                max_iterations,
+                convergence,
+            );
+            halftone.chunks(3).map(|c| (c[0], c[1], c[2])).collect()
+        }
+    };
+
+    for (&(x, y), &rgb) in coordinates.iter().zip(&refined) {
+        frame.set_rgb(x, y, rgb);
+    }
+}
+
+/// Quantize every pixel of frame in scan order with quantize_pixel, spreading
+/// each pixel's quantization error to its neighbors according to kernel.
+fn diffuse_error(
+    frame: &mut FloatFrame,
+    kernel: &DiffusionKernel,
+    scan: ScanOrder,
+    quantize_pixel: impl Fn(RgbPixel) -> RgbPixel,
+) {
+    let coefficients = kernel.coefficients();
+    let mirrored_coefficients: Vec<((isize, isize), f32)> = coefficients
+        .iter()
+        .map(|&((ox, oy), coef)| ((-ox, oy), coef))
+        .collect();
+
+    for y in 0..frame.height {
+        let reverse = scan == ScanOrder::Serpentine && y % 2 == 1;
+        let coefficients = if reverse {
+            &mirrored_coefficients
+        } else {
+            &coefficients
+        };
+
+        for i in 0..frame.width {
+            let x = if reverse { frame.width - 1 - i } else { i };
+            let (r, g, b) = frame.get_rgb(x, y).unwrap();
+            let (quantized_r, quantized_g, quantized_b) =
+                quantize_pixel((r, g, b));
 
             frame.set_rgb(x, y, (quantized_r, quantized_g, quantized_b));
 
@@ -150,7 +983,7 @@ pub fn dither_frame_floyd_steinberg_color(
             let err_g = g - quantized_g;
             let err_b = b - quantized_b;
 
-            for ((ox, oy), coef) in OFFSET_COEF {
+            for &((ox, oy), coef) in coefficients {
                 let nx = x + ox;
                 let ny = y + oy;
                 if let Some((r, g, b)) = frame.get_rgb(nx, ny) {
@@ -165,6 +998,22 @@ pub fn dither_frame_floyd_steinberg_color(
     }
 }
 
+fn quantize_pixel(rgb: RgbPixel, gap: f32, mode: ColorMode) -> RgbPixel {
+    match mode {
+        ColorMode::Gray => quantize_gray(rgb, gap),
+        ColorMode::Rgb => quantize_rgb(rgb, gap),
+    }
+}
+
+fn quantize_gray(rgb: RgbPixel, gap: f32) -> RgbPixel {
+    let quantized = quantize(luma(rgb), gap);
+    (quantized, quantized, quantized)
+}
+
+fn quantize_rgb((r, g, b): RgbPixel, gap: f32) -> RgbPixel {
+    (quantize(r, gap), quantize(g, gap), quantize(b, gap))
+}
+
 fn quantize_gap(palette_count: u32) -> f32 {
     255.0 / (palette_count as f32 - 1.0)
 }
@@ -172,3 +1021,738 @@ fn quantize_gap(palette_count: u32) -> f32 {
 fn quantize(color: f32, gap: f32) -> f32 {
     (color / gap + 0.5).floor() * gap
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SIZE: isize = 64;
+
+    fn gray_buffer(value: u8) -> Vec<u8> {
+        vec![value; (SIZE * SIZE * 3) as usize]
+    }
+
+    fn mean(buffer: &[u8]) -> f32 {
+        buffer.iter().map(|&v| v as f32).sum::<f32>() / buffer.len() as f32
+    }
+
+    fn dither_buffer(
+        width: isize,
+        height: isize,
+        buffer: &mut [u8],
+        dither: impl FnOnce(&mut FloatFrame),
+    ) {
+        let mut output = Frame::new(width, height, buffer);
+        let mut frame = FloatFrame::from_frame(&output);
+        dither(&mut frame);
+        frame.write_to(&mut output);
+    }
+
+    fn uniform(palette_count: u32, mode: ColorMode) -> Quantizer {
+        Quantizer::Uniform {
+            palette_count,
+            mode,
+        }
+    }
+
+    #[test]
+    fn atkinson_mid_gray_averages_to_input() {
+        let mut buf = gray_buffer(128);
+        dither_buffer(SIZE, SIZE, &mut buf, |frame| {
+            dither_frame_atkinson(
+                frame,
+                &uniform(2, ColorMode::Gray),
+                ScanOrder::Raster,
+            )
+        });
+
+        assert!(buf.iter().all(|&v| v == 0 || v == 255));
+        assert!((mean(&buf) - 128.0).abs() < 255.0 * 0.02);
+    }
+
+    #[test]
+    fn floyd_steinberg_mid_gray_averages_to_input() {
+        let mut buf = gray_buffer(128);
+        dither_buffer(SIZE, SIZE, &mut buf, |frame| {
+            dither_frame_floyd_steinberg_color(
+                frame,
+                &uniform(2, ColorMode::Rgb),
+                ScanOrder::Raster,
+            )
+        });
+
+        assert!(buf.iter().all(|&v| v == 0 || v == 255));
+        assert!((mean(&buf) - 128.0).abs() < 255.0 * 0.01);
+    }
+
+    #[test]
+    fn floyd_steinberg_keeps_density_of_shadows_and_highlights() {
+        for value in [8u8, 24, 232, 248] {
+            let mut buf = gray_buffer(value);
+            dither_buffer(SIZE, SIZE, &mut buf, |frame| {
+                dither_frame_floyd_steinberg_color(
+                    frame,
+                    &uniform(2, ColorMode::Rgb),
+                    ScanOrder::Raster,
+                )
+            });
+
+            assert!(
+                (mean(&buf) - value as f32).abs() < 255.0 * 0.01,
+                "value {} averaged to {}",
+                value,
+                mean(&buf)
+            );
+        }
+    }
+
+    #[test]
+    fn floyd_steinberg_mid_gray_between_levels_averages_to_input() {
+        let mut buf = gray_buffer(100);
+        dither_buffer(SIZE, SIZE, &mut buf, |frame| {
+            dither_frame_floyd_steinberg_color(
+                frame,
+                &uniform(4, ColorMode::Rgb),
+                ScanOrder::Raster,
+            )
+        });
+
+        assert!(
+            buf.iter()
+                .all(|&v| v == 0 || v == 85 || v == 170 || v == 255)
+        );
+        assert!((mean(&buf) - 100.0).abs() < 255.0 * 0.01);
+    }
+
+    #[test]
+    fn error_diffusion_named_kernels_keep_average_density() {
+        let kernels = [
+            DiffusionKernel::jarvis_judice_ninke(),
+            DiffusionKernel::stucki(),
+            DiffusionKernel::burkes(),
+            DiffusionKernel::sierra(),
+            DiffusionKernel::two_row_sierra(),
+            DiffusionKernel::sierra_lite(),
+        ];
+
+        for kernel in kernels {
+            for scan in [ScanOrder::Raster, ScanOrder::Serpentine] {
+                let mut buf = gray_buffer(72);
+                dither_buffer(SIZE, SIZE, &mut buf, |frame| {
+                    dither_frame_error_diffusion(
+                        frame,
+                        &kernel,
+                        &uniform(2, ColorMode::Rgb),
+                        scan,
+                    )
+                });
+
+                assert!(buf.iter().all(|&v| v == 0 || v == 255));
+                assert!(
+                    (mean(&buf) - 72.0).abs() < 255.0 * 0.01,
+                    "{:?} {:?} averaged to {}",
+                    kernel,
+                    scan,
+                    mean(&buf)
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn serpentine_scans_odd_rows_right_to_left() {
+        // Only the second row carries any value, so its dither pattern is
+        // determined solely by the direction it is scanned in.
+        let mut buf = vec![0u8; 8 * 3];
+        buf.extend(vec![100u8; 8 * 3]);
+        let mut raster = buf.clone();
+        let mut serpentine = buf;
+
+        dither_buffer(8, 2, &mut raster, |frame| {
+            dither_frame_floyd_steinberg_color(
+                frame,
+                &uniform(2, ColorMode::Rgb),
+                ScanOrder::Raster,
+            )
+        });
+        dither_buffer(8, 2, &mut serpentine, |frame| {
+            dither_frame_floyd_steinberg_color(
+                frame,
+                &uniform(2, ColorMode::Rgb),
+                ScanOrder::Serpentine,
+            )
+        });
+
+        let raster_row: Vec<&[u8]> = raster[8 * 3..].chunks(3).collect();
+        let mut serpentine_row: Vec<&[u8]> =
+            serpentine[8 * 3..].chunks(3).collect();
+        serpentine_row.reverse();
+        assert_eq!(raster_row, serpentine_row);
+        assert_ne!(raster, serpentine);
+    }
+
+    #[test]
+    fn ordered_keeps_average_density() {
+        let matrix = ThresholdMatrix::bayer(8);
+
+        for value in [16u8, 100, 128, 200] {
+            for mode in [ColorMode::Gray, ColorMode::Rgb] {
+                let mut buf = gray_buffer(value);
+                dither_buffer(SIZE, SIZE, &mut buf, |frame| {
+                    dither_frame_ordered(frame, &matrix, &uniform(2, mode))
+                });
+
+                assert!(buf.iter().all(|&v| v == 0 || v == 255));
+                assert!(
+                    (mean(&buf) - value as f32).abs() < 255.0 / 64.0,
+                    "value {} averaged to {}",
+                    value,
+                    mean(&buf)
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn ordered_gray_mode_outputs_gray() {
+        let matrix = ThresholdMatrix::bayer(4);
+        let mut buf: Vec<u8> =
+            (0..SIZE * SIZE * 3).map(|i| (i * 7 % 256) as u8).collect();
+        dither_buffer(SIZE, SIZE, &mut buf, |frame| {
+            dither_frame_ordered(frame, &matrix, &uniform(4, ColorMode::Gray))
+        });
+
+        for pixel in buf.chunks(3) {
+            assert!(pixel[0] == pixel[1] && pixel[1] == pixel[2]);
+            assert!([0, 85, 170, 255].contains(&pixel[0]));
+        }
+    }
+
+    #[test]
+    fn halftone_keeps_average_density() {
+        use crate::halftone::{DotShape, Screen};
+
+        let screens = [
+            Halftone::Gray(Screen::new(DotShape::Round, 8.0, 45.0)),
+            Halftone::Cmyk {
+                cyan: Screen::new(DotShape::Round, 8.0, 15.0),
+                magenta: Screen::new(DotShape::Round, 8.0, 75.0),
+                yellow: Screen::new(DotShape::Round, 8.0, 0.0),
+                black: Screen::new(DotShape::Round, 8.0, 45.0),
+            },
+        ];
+
+        for screens in screens {
+            let mut buf = gray_buffer(96);
+            dither_buffer(SIZE, SIZE, &mut buf, |frame| {
+                dither_frame_halftone(frame, &screens, None)
+            });
+
+            assert!(buf.iter().all(|&v| v == 0 || v == 255));
+            assert!(
+                (mean(&buf) - 96.0).abs() < 255.0 * 0.03,
+                "{:?} averaged to {}",
+                screens,
+                mean(&buf)
+            );
+        }
+    }
+
+    #[test]
+    fn riemersma_keeps_average_density() {
+        for value in [32u8, 128, 220] {
+            for mode in [ColorMode::Gray, ColorMode::Rgb] {
+                // Non-power-of-two so the curve leaves and re-enters the frame.
+                let (width, height) = (SIZE - 7, SIZE + 5);
+                let mut buf = vec![value; (width * height * 3) as usize];
+                dither_buffer(width, height, &mut buf, |frame| {
+                    dither_frame_riemersma(
+                        frame,
+                        16,
+                        1.0 / 16.0,
+                        &uniform(2, mode),
+                    )
+                });
+
+                assert!(buf.iter().all(|&v| v == 0 || v == 255));
+                assert!(
+                    (mean(&buf) - value as f32).abs() < 255.0 * 0.01,
+                    "value {} averaged to {}",
+                    value,
+                    mean(&buf)
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn variable_diffusion_keeps_average_density() {
+        for kernel in [VariableKernel::Ostromoukhov, VariableKernel::ZhouFang] {
+            for value in [16u8, 90, 128, 240] {
+                let mut buf = gray_buffer(value);
+                dither_buffer(SIZE, SIZE, &mut buf, |frame| {
+                    dither_frame_variable_diffusion(
+                        frame,
+                        kernel,
+                        &uniform(2, ColorMode::Rgb),
+                        ScanOrder::Serpentine,
+                    )
+                });
+
+                assert!(buf.iter().all(|&v| v == 0 || v == 255));
+                assert!(
+                    (mean(&buf) - value as f32).abs() < 255.0 * 0.01,
+                    "{:?} {} averaged to {}",
+                    kernel,
+                    value,
+                    mean(&buf)
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn dot_diffusion_keeps_average_density() {
+        for value in [24u8, 128, 200] {
+            for mode in [ColorMode::Gray, ColorMode::Rgb] {
+                let mut buf = gray_buffer(value);
+                dither_buffer(SIZE, SIZE, &mut buf, |frame| {
+                    dither_frame_dot_diffusion(
+                        frame,
+                        &ClassMatrix::knuth(),
+                        &uniform(2, mode),
+                    )
+                });
+
+                assert!(buf.iter().all(|&v| v == 0 || v == 255));
+                assert!(
+                    (mean(&buf) - value as f32).abs() < 255.0 * 0.02,
+                    "value {} averaged to {}",
+                    value,
+                    mean(&buf)
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn dbs_refines_initial_dither() {
+        let initial = DitherAlgoOpts::Atkinson {
+            quantizer: uniform(2, ColorMode::Gray),
+            scan: ScanOrder::Raster,
+        };
+        let size = 24;
+        let mut buf = vec![96u8; size * size * 3];
+        dither_buffer(size as isize, size as isize, &mut buf, |frame| {
+            dither_frame_dbs(
+                frame,
+                &initial,
+                10,
+                0.001,
+                &uniform(2, ColorMode::Gray),
+            )
+        });
+
+        assert!(buf.iter().all(|&v| v == 0 || v == 255));
+        assert!((mean(&buf) - 96.0).abs() < 255.0 * 0.02);
+    }
+
+    #[test]
+    fn pattern_preserves_density_with_custom_palette() {
+        let palette = Palette::parse("#000000 #ffffff #ff0000").unwrap();
+        for method in [
+            PatternMethod::Yliluoma1,
+            PatternMethod::Yliluoma2,
+            PatternMethod::Knoll,
+        ] {
+            let mut buf = gray_buffer(96);
+            dither_buffer(SIZE, SIZE, &mut buf, |frame| {
+                dither_frame_pattern(
+                    frame,
+                    method,
+                    &palette,
+                    &ThresholdMatrix::bayer(8),
+                    &PlanCache::default(),
+                )
+            });
+
+            assert!(buf.chunks(3).all(|px| {
+                let rgb = (px[0] as f32, px[1] as f32, px[2] as f32);
+                palette.colors().contains(&rgb)
+            }));
+            assert!(
+                (mean(&buf) - 96.0).abs() < 255.0 * 0.03,
+                "{:?} averaged to {}",
+                method,
+                mean(&buf)
+            );
+        }
+    }
+
+    #[test]
+    fn random_preserves_density() {
+        for distribution in
+            [NoiseDistribution::Uniform, NoiseDistribution::Triangular]
+        {
+            let mut buf = gray_buffer(96);
+            dither_buffer(SIZE, SIZE, &mut buf, |frame| {
+                dither_frame_random(
+                    frame,
+                    distribution,
+                    0,
+                    &uniform(2, ColorMode::Gray),
+                )
+            });
+
+            assert!(buf.iter().all(|&v| v == 0 || v == 255));
+            assert!(
+                (mean(&buf) - 96.0).abs() < 255.0 * 0.03,
+                "{:?} averaged to {}",
+                distribution,
+                mean(&buf)
+            );
+        }
+    }
+
+    #[test]
+    fn random_is_deterministic_per_seed() {
+        let algo = |reseed_per_frame| DitherAlgoOpts::Random {
+            distribution: NoiseDistribution::Uniform,
+            seed: 7,
+            reseed_per_frame,
+            quantizer: uniform(2, ColorMode::Rgb),
+        };
+        let dither = |algo: &DitherAlgoOpts, frame_index| {
+            let mut buf = gray_buffer(128);
+            dither_frame(SIZE, SIZE, &mut buf, algo, frame_index);
+            buf
+        };
+
+        assert_eq!(dither(&algo(false), 0), dither(&algo(false), 0));
+        assert_eq!(dither(&algo(false), 0), dither(&algo(false), 1));
+        assert_eq!(dither(&algo(true), 3), dither(&algo(true), 3));
+        assert_ne!(dither(&algo(true), 0), dither(&algo(true), 1));
+    }
+
+    #[test]
+    fn palette_quantizer_only_outputs_palette_colors() {
+        let palette =
+            Palette::parse("#000000 #ffffff #ff0000 #0000ff").unwrap();
+        let quantizer = Quantizer::Palette(palette.clone());
+        let algos = [
+            DitherAlgoOpts::ErrorDiffusion {
+                kernel: DiffusionKernel::floyd_steinberg(),
+                quantizer: quantizer.clone(),
+                scan: ScanOrder::Serpentine,
+            },
+            DitherAlgoOpts::DotDiffusion {
+                classes: ClassMatrix::knuth(),
+                quantizer: quantizer.clone(),
+            },
+            DitherAlgoOpts::Riemersma {
+                queue_length: 16,
+                ratio: 1.0 / 16.0,
+                quantizer: quantizer.clone(),
+            },
+        ];
+
+        for algo in algos {
+            // Purple, which only mixes from red and blue.
+            let mut buf = [128u8, 0, 128].repeat((SIZE * SIZE) as usize);
+            dither_frame(SIZE, SIZE, &mut buf, &algo, 0);
+
+            assert!(buf.chunks(3).all(|px| {
+                let rgb = (px[0] as f32, px[1] as f32, px[2] as f32);
+                palette.colors().contains(&rgb)
+            }));
+            for (c, expected) in [128.0, 0.0, 128.0].into_iter().enumerate() {
+                let channel: Vec<u8> =
+                    buf.iter().skip(c).step_by(3).copied().collect();
+                assert!(
+                    (mean(&channel) - expected).abs() < 255.0 * 0.03,
+                    "{:?} averaged channel {} to {}",
+                    algo,
+                    c,
+                    mean(&channel)
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn linear_mid_gray_keeps_its_brightness() {
+        let mut buf = gray_buffer(128);
+        let algo = DitherAlgoOpts::FsColor {
+            quantizer: uniform(2, ColorMode::Rgb),
+            scan: ScanOrder::Raster,
+        };
+        dither_frame_linear(SIZE, SIZE, &mut buf, &algo.linearized(), 0);
+
+        // sRGB 128 is about 21.6% of full intensity in linear light.
+        assert!(buf.iter().all(|&v| v == 0 || v == 255));
+        assert!((mean(&buf) - 255.0 * 0.216).abs() < 255.0 * 0.01);
+    }
+
+    #[test]
+    fn linear_keeps_density_of_shadows() {
+        let (value, size) = (40, 256);
+        let mut buf = vec![value; (size * size * 3) as usize];
+        let algo = DitherAlgoOpts::Random {
+            distribution: NoiseDistribution::Uniform,
+            seed: 0,
+            reseed_per_frame: false,
+            quantizer: uniform(2, ColorMode::Rgb),
+        };
+        dither_frame_linear(size, size, &mut buf, &algo.linearized(), 0);
+
+        // Rounded to u8, sRGB 40 would be linear 5 rather than 5.41.
+        let expected = decode_srgb((value as f32, 0.0, 0.0)).0;
+        let lit = buf.iter().filter(|&&v| v == 255).count() as f32;
+        let linear_mean = 255.0 * lit / buf.len() as f32;
+        assert!(
+            (linear_mean - expected).abs() < expected * 0.03,
+            "{} instead of {}",
+            linear_mean,
+            expected
+        );
+    }
+
+    #[test]
+    fn linear_outputs_only_original_levels_and_colors() {
+        let palette =
+            Palette::parse("#000000 #111111 #ff0000 #ffffff").unwrap();
+        let algos = [
+            DitherAlgoOpts::Atkinson {
+                quantizer: uniform(4, ColorMode::Gray),
+                scan: ScanOrder::Raster,
+            },
+            DitherAlgoOpts::Ordered {
+                matrix: ThresholdMatrix::bayer(4),
+                quantizer: uniform(4, ColorMode::Rgb),
+            },
+            DitherAlgoOpts::Riemersma {
+                queue_length: 16,
+                ratio: 0.125,
+                quantizer: Quantizer::Palette(palette.clone()),
+            },
+            DitherAlgoOpts::Pattern {
+                method: PatternMethod::Knoll,
+                palette: palette.clone(),
+                matrix: ThresholdMatrix::bayer(4),
+                plans: PlanCache::default(),
+            },
+        ];
+
+        for algo in algos {
+            let mut buf: Vec<u8> =
+                (0..SIZE * SIZE * 3).map(|i| (i / 3 % 256) as u8).collect();
+            dither_frame_linear(SIZE, SIZE, &mut buf, &algo.linearized(), 0);
+
+            let is_output = |rgb: &[u8]| match &algo {
+                DitherAlgoOpts::Riemersma { .. }
+                | DitherAlgoOpts::Pattern { .. } => palette
+                    .colors()
+                    .contains(&(rgb[0] as f32, rgb[1] as f32, rgb[2] as f32)),
+                _ => rgb.iter().all(|&v| v % 85 == 0),
+            };
+            assert!(buf.chunks(3).all(is_output), "{:?}", algo);
+        }
+    }
+
+    #[test]
+    fn per_channel_levels_only_output_their_levels() {
+        let levels = ChannelLevels::parse("rgb332").unwrap();
+        let mut buf = gray_buffer(100);
+        dither_buffer(SIZE, SIZE, &mut buf, |frame| {
+            dither_frame_floyd_steinberg_color(
+                frame,
+                &Quantizer::Levels {
+                    levels: levels.clone(),
+                    mode: ColorMode::Rgb,
+                },
+                ScanOrder::Raster,
+            )
+        });
+
+        for pixel in buf.chunks(3) {
+            for (c, &v) in pixel.iter().enumerate() {
+                assert!(
+                    levels.channel(c).iter().any(|l| l.round() == v as f32)
+                );
+            }
+        }
+        for c in 0..3 {
+            let channel: Vec<u8> =
+                buf.iter().skip(c).step_by(3).copied().collect();
+            assert!((mean(&channel) - 100.0).abs() < 255.0 * 0.01);
+        }
+    }
+
+    #[test]
+    fn ordered_keeps_mean_tone_of_uneven_levels() {
+        let matrix = ThresholdMatrix::bayer(8);
+
+        for levels in ["rgb332", "0,96,255"] {
+            let quantizer = Quantizer::Levels {
+                levels: ChannelLevels::parse(levels).unwrap(),
+                mode: ColorMode::Rgb,
+            };
+            for value in [40u8, 100, 150, 220] {
+                let mut buf = gray_buffer(value);
+                dither_buffer(SIZE, SIZE, &mut buf, |frame| {
+                    dither_frame_ordered(frame, &matrix, &quantizer)
+                });
+
+                for c in 0..3 {
+                    let channel: Vec<u8> =
+                        buf.iter().skip(c).step_by(3).copied().collect();
+                    assert!(
+                        (mean(&channel) - value as f32).abs() < 255.0 / 128.0,
+                        "{} channel {} of {} averaged to {}",
+                        levels,
+                        c,
+                        value,
+                        mean(&channel)
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn linear_ordered_keeps_mean_tone_between_levels() {
+        let algo = DitherAlgoOpts::Ordered {
+            matrix: ThresholdMatrix::bayer(8),
+            quantizer: uniform(4, ColorMode::Rgb),
+        };
+
+        for value in [60u8, 100, 180] {
+            let mut buf = gray_buffer(value);
+            dither_frame_linear(SIZE, SIZE, &mut buf, &algo.linearized(), 0);
+
+            let linear: Vec<f32> = buf
+                .iter()
+                .map(|&v| decode_srgb((v as f32, 0.0, 0.0)).0)
+                .collect();
+            let linear_mean = linear.iter().sum::<f32>() / linear.len() as f32;
+            let expected = decode_srgb((value as f32, 0.0, 0.0)).0;
+            assert!(
+                (linear_mean - expected).abs() < 255.0 / 128.0,
+                "{} averaged to {} instead of {}",
+                value,
+                linear_mean,
+                expected
+            );
+        }
+    }
+
+    #[test]
+    fn gray_mode_outputs_gray_with_per_channel_levels() {
+        let levels = ChannelLevels::parse("rgb242").unwrap();
+        let quantizer = Quantizer::Levels {
+            levels: levels.shared().unwrap(),
+            mode: ColorMode::Gray,
+        };
+        let source: Vec<u8> =
+            (0..16 * 16 * 3).map(|i| (i * 7 % 256) as u8).collect();
+        let algos = [
+            DitherAlgoOpts::FsColor {
+                quantizer: quantizer.clone(),
+                scan: ScanOrder::Raster,
+            },
+            DitherAlgoOpts::Ordered {
+                matrix: ThresholdMatrix::bayer(4),
+                quantizer: quantizer.clone(),
+            },
+            DitherAlgoOpts::Dbs {
+                initial: Box::new(DitherAlgoOpts::Ordered {
+                    matrix: ThresholdMatrix::bayer(4),
+                    quantizer: quantizer.clone(),
+                }),
+                max_iterations: 2,
+                convergence: 0.0,
+                quantizer: quantizer.clone(),
+            },
+        ];
+
+        for algo in algos {
+            let mut buf = source.clone();
+            dither_frame(16, 16, &mut buf, &algo, 0);
+            assert!(
+                buf.chunks(3).all(|p| p[0] == p[1] && p[1] == p[2]),
+                "{:?}",
+                algo
+            );
+            assert!(
+                buf.chunks(3).all(|p| (0..3)
+                    .all(|c| { levels.channel(c).contains(&(p[c] as f32)) })),
+                "{:?}",
+                algo
+            );
+        }
+    }
+
+    #[test]
+    fn color_mode_applies_to_every_diffusion_algorithm() {
+        let purple: Vec<u8> = [160u8, 40, 200].repeat((SIZE * SIZE) as usize);
+
+        let mut buf = purple.clone();
+        dither_buffer(SIZE, SIZE, &mut buf, |frame| {
+            dither_frame_atkinson(
+                frame,
+                &uniform(2, ColorMode::Rgb),
+                ScanOrder::Raster,
+            )
+        });
+        assert!(buf.chunks(3).any(|p| p[0] != p[1]));
+
+        let mut buf = purple.clone();
+        dither_buffer(SIZE, SIZE, &mut buf, |frame| {
+            dither_frame_floyd_steinberg_color(
+                frame,
+                &uniform(2, ColorMode::Gray),
+                ScanOrder::Raster,
+            )
+        });
+        assert!(buf.chunks(3).all(|p| p[0] == p[1] && p[1] == p[2]));
+        let luma = luma((160.0, 40.0, 200.0));
+        assert!((mean(&buf) - luma).abs() < 255.0 * 0.01);
+    }
+
+    #[test]
+    fn with_palette_replaces_only_palettes() {
+        let old = Palette::parse("#000000 #ffffff").unwrap();
+        let new = Palette::parse("#ff0000 #0000ff").unwrap();
+        let algo = DitherAlgoOpts::Dbs {
+            initial: Box::new(DitherAlgoOpts::Atkinson {
+                quantizer: Quantizer::Palette(old.clone()),
+                scan: ScanOrder::Raster,
+            }),
+            max_iterations: 1,
+            convergence: 0.0,
+            quantizer: uniform(2, ColorMode::Rgb),
+        };
+
+        match algo.with_palette(&new) {
+            DitherAlgoOpts::Dbs {
+                initial, quantizer, ..
+            } => {
+                assert!(matches!(
+                    *initial,
+                    DitherAlgoOpts::Atkinson {
+                        quantizer: Quantizer::Palette(palette),
+                        ..
+                    } if palette == new
+                ));
+                assert!(matches!(
+                    quantizer,
+                    Quantizer::Uniform {
+                        palette_count: 2,
+                        ..
+                    }
+                ));
+            }
+            _ => unreachable!(),
+        }
+    }
+}
@@ -0,0 +1,329 @@
+use std::fs;
+use std::sync::{Barrier, RwLock};
+use std::thread;
+
+use crate::frame::RgbPixel;
+
+/// A tileable matrix assigning every pixel a class in [0, N). Dot diffusion
+/// quantizes pixels class by class, diffusing each pixel's error only to
+/// neighbors of a higher class.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ClassMatrix {
+    pub width: usize,
+    pub height: usize,
+    classes: Vec<usize>,
+}
+
+impl ClassMatrix {
+    /// Build a matrix from row-major classes, which must be a permutation of
+    /// [0, width * height).
+    pub fn new(
+        width: usize,
+        height: usize,
+        classes: Vec<usize>,
+    ) -> Result<Self, String> {
+        if width == 0 || height == 0 || classes.len() != width * height {
+            return Err(format!(
+                "Expected {}x{} classes, got {}",
+                width,
+                height,
+                classes.len()
+            ));
+        }
+        let mut seen = vec![false; classes.len()];
+        for &class in &classes {
+            if class >= classes.len() || seen[class] {
+                return Err(format!(
+                    "Classes must be a permutation of 0 to {}",
+                    classes.len() - 1
+                ));
+            }
+            seen[class] = true;
+        }
+
+        Ok(ClassMatrix {
+            width,
+            height,
+            classes,
+        })
+    }
+
+    /// The 8x8 class matrix from "Digital Halftones by Dot Diffusion", Knuth
+    /// 1987.
+    pub fn knuth() -> Self {
+        Self::new(8, 8, KNUTH.to_vec()).unwrap()
+    }
+
+    /// Parse whitespace separated classes, one row per line.
+    pub fn parse(s: &str) -> Result<Self, String> {
+        let mut rows = Vec::new();
+        for line in s.lines().filter(|line| !line.trim().is_empty()) {
+            let row = line
+                .split_whitespace()
+                .map(|c| c.parse::<usize>())
+                .collect::<Result<Vec<usize>, _>>()
+                .map_err(|_| format!("Invalid class in row '{}'", line))?;
+            rows.push(row);
+        }
+
+        let width = rows.first().map_or(0, |row| row.len());
+        if rows.iter().any(|row| row.len() != width) {
+            return Err("Every row must have the same length".into());
+        }
+        let height = rows.len();
+        Self::new(width, height, rows.concat())
+    }
+
+    /// Read and [ClassMatrix::parse] a class matrix file.
+    pub fn load(path: &str) -> Result<Self, String> {
+        let contents = fs::read_to_string(path).map_err(|e| {
+            format!("Reading class matrix '{}' failed: {}", path, e)
+        })?;
+        Self::parse(&contents)
+            .map_err(|e| format!("Invalid class matrix '{}': {}", path, e))
+    }
+
+    pub fn count(&self) -> usize {
+        self.classes.len()
+    }
+
+    /// Class at x, y of a frame with this matrix tiled over it.
+    pub fn get(&self, x: isize, y: isize) -> usize {
+        let mx = x.rem_euclid(self.width as isize) as usize;
+        let my = y.rem_euclid(self.height as isize) as usize;
+        self.classes[my * self.width + mx]
+    }
+
+    /// Columns within the matrix's row for frame row y that have class.
+    fn columns_of(&self, class: usize, y: isize) -> Vec<isize> {
+        let my = y.rem_euclid(self.height as isize) as usize;
+        (0..self.width)
+            .filter(|&mx| self.classes[my * self.width + mx] == class)
+            .map(|mx| mx as isize)
+            .collect()
+    }
+}
+
+/// Quantize a row-major width x height frame of pixels in place with dot
+/// diffusion, splitting the frame into bands of rows that each thread steps
+/// through every class of. The result doesn't depend on the number of threads.
+pub fn diffuse(
+    pixels: &mut [RgbPixel],
+    width: isize,
+    height: isize,
+    classes: &ClassMatrix,
+    threads: usize,
+    quantize_pixel: impl Fn(RgbPixel) -> RgbPixel + Sync,
+) {
+    assert!(pixels.len() == (width * height) as usize);
+    if pixels.is_empty() {
+        return;
+    }
+
+    let row_len = width as usize;
+    let rows_per_band = (height as usize).div_ceil(threads.max(1));
+    let band_len = rows_per_band * row_len;
+    // Columns of every class within every row of the matrix.
+    let columns: Vec<Vec<Vec<isize>>> = (0..classes.count())
+        .map(|class| {
+            (0..classes.height as isize)
+                .map(|y| classes.columns_of(class, y))
+                .collect()
+        })
+        .collect();
+    let class_pixels = |class: usize, y: isize| {
+        let columns = &columns[class][y as usize % classes.height];
+        let tile_width = classes.width;
+        columns
+            .iter()
+            .flat_map(move |&mx| (mx..width).step_by(tile_width))
+    };
+    // Error of each pixel of the current class divided by the total weight of
+    // the neighbors it is diffused to, by band. Each band is only written by
+    // its own thread, and only read by others after every thread is done
+    // writing.
+    let pending: Vec<RwLock<Vec<RgbPixel>>> = pixels
+        .chunks(band_len)
+        .map(|band| RwLock::new(vec![(0.0, 0.0, 0.0); band.len()]))
+        .collect();
+    let barrier = Barrier::new(pending.len());
+
+    thread::scope(|s| {
+        for (band, pixel_band) in pixels.chunks_mut(band_len).enumerate() {
+            let quantize_pixel = &quantize_pixel;
+            let class_pixels = &class_pixels;
+            let pending = &pending;
+            let barrier = &barrier;
+            s.spawn(move || {
+                let first_row = (band * rows_per_band) as isize;
+                let rows = (pixel_band.len() / row_len) as isize;
+
+                for class in 0..classes.count() {
+                    // Quantize every pixel of the class in the band.
+                    {
+                        let mut pending_band = pending[band].write().unwrap();
+                        for y in first_row..first_row + rows {
+                            for x in class_pixels(class, y) {
+                                let i = ((y - first_row) * width + x) as usize;
+                                let (r, g, b) = pixel_band[i];
+                                let quantized = quantize_pixel((r, g, b));
+                                pixel_band[i] = quantized;
+
+                                let weight = neighbor_weight(
+                                    classes,
+                                    class,
+                                    (x, y),
+                                    (width, height),
+                                );
+                                pending_band[i] = if weight > 0.0 {
+                                    (
+                                        (r - quantized.0) / weight,
+                                        (g - quantized.1) / weight,
+                                        (b - quantized.2) / weight,
+                                    )
+                                } else {
+                                    (0.0, 0.0, 0.0)
+                                };
+                            }
+                        }
+                    }
+                    barrier.wait();
+
+                    // Pull the class's error into neighbors of higher class in
+                    // the band, from the band's and its neighbor bands' pixels.
+                    {
+                        let bands: Vec<_> = pending
+                            .iter()
+                            .map(|band| band.read().unwrap())
+                            .collect();
+                        let pending_at = |x: isize, y: isize| {
+                            let band = y as usize / rows_per_band;
+                            let y = y as usize - band * rows_per_band;
+                            bands[band][y * row_len + x as usize]
+                        };
+                        for y in first_row..first_row + rows {
+                            for sy in (y - 1).max(0)..(y + 2).min(height) {
+                                for sx in class_pixels(class, sy) {
+                                    let (er, eg, eb) = pending_at(sx, sy);
+                                    for x in
+                                        (sx - 1).max(0)..(sx + 2).min(width)
+                                    {
+                                        if classes.get(x, y) <= class {
+                                            continue;
+                                        }
+                                        let w = weight(x - sx, y - sy);
+                                        let i = ((y - first_row) * width + x)
+                                            as usize;
+                                        let (r, g, b) = pixel_band[i];
+                                        pixel_band[i] = (
+                                            r + er * w,
+                                            g + eg * w,
+                                            b + eb * w,
+                                        );
+                                    }
+                                }
+                            }
+                        }
+                    }
+                    barrier.wait();
+                }
+            });
+        }
+    });
+}
+
+/// Total weight of the neighbors of x, y within the frame that have a higher
+/// class than class.
+fn neighbor_weight(
+    classes: &ClassMatrix,
+    class: usize,
+    (x, y): (isize, isize),
+    (width, height): (isize, isize),
+) -> f32 {
+    let mut total = 0.0;
+    for ny in (y - 1).max(0)..(y + 2).min(height) {
+        for nx in (x - 1).max(0)..(x + 2).min(width) {
+            if classes.get(nx, ny) > class {
+                total += weight(nx - x, ny - y);
+            }
+        }
+    }
+    total
+}
+
+/// Orthogonal neighbors receive twice the error of diagonal ones.
+fn weight(dx: isize, dy: isize) -> f32 {
+    if dx == 0 || dy == 0 { 2.0 } else { 1.0 }
+}
+
+#[rustfmt::skip]
+const KNUTH: [usize; 64] = [
+    34, 48, 40, 32, 29, 15, 23, 31,
+    42, 58, 56, 53, 21,  5,  7, 10,
+    50, 62, 61, 45, 13,  1,  2, 18,
+    38, 46, 54, 37, 25, 17,  9, 26,
+    28, 14, 22, 30, 35, 49, 41, 33,
+    20,  4,  6, 11, 43, 59, 57, 52,
+    12,  0,  3, 19, 51, 63, 60, 44,
+    24, 16,  8, 27, 39, 47, 55, 36,
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn threshold((r, g, b): RgbPixel) -> RgbPixel {
+        let t = |v: f32| if v < 127.5 { 0.0 } else { 255.0 };
+        (t(r), t(g), t(b))
+    }
+
+    #[test]
+    fn knuth_is_a_permutation() {
+        let matrix = ClassMatrix::knuth();
+        assert_eq!(matrix.count(), 64);
+    }
+
+    #[test]
+    fn parse_reads_rows() {
+        let matrix = ClassMatrix::parse("0 2\n3 1\n").unwrap();
+        assert_eq!(matrix.get(1, 0), 2);
+        assert_eq!(matrix.get(3, 3), 1);
+    }
+
+    #[test]
+    fn parse_rejects_invalid_matrices() {
+        for s in ["", "0 1\n2", "0 1\n1 2", "0 x\n2 3", "0 1 2\n4 5 6"] {
+            assert!(ClassMatrix::parse(s).is_err(), "{:?}", s);
+        }
+    }
+
+    #[test]
+    fn diffuse_is_independent_of_thread_count() {
+        let (width, height) = (37, 29);
+        let source: Vec<RgbPixel> = (0..width * height)
+            .map(|i| ((i * 7 % 256) as f32, 100.0, (i % 200) as f32))
+            .collect();
+
+        let mut single = source.clone();
+        diffuse(
+            &mut single,
+            width,
+            height,
+            &ClassMatrix::knuth(),
+            1,
+            threshold,
+        );
+        for threads in [2, 3, 8] {
+            let mut parallel = source.clone();
+            diffuse(
+                &mut parallel,
+                width,
+                height,
+                &ClassMatrix::knuth(),
+                threads,
+                threshold,
+            );
+            assert_eq!(single, parallel, "{} threads", threads);
+        }
+    }
+}
@@ -6,7 +6,7 @@ pub fn spawn_frame_reader(
     output_height: isize,
 ) -> Result<ChildStdout, String> {
     let mut child = Command::new("ffmpeg")
-        .args(&[
+        .args([
             "-v",
             "error",
             "-i",
@@ -35,7 +35,7 @@ pub fn spawn_frame_writer_child(
     path: &str,
 ) -> Result<Child, String> {
     let child = Command::new("ffmpeg")
-        .args(&[
+        .args([
             "-v",
             "error",
             "-f",
@@ -64,10 +64,48 @@ pub fn spawn_frame_writer_child(
     Ok(child)
 }
 
+/// Read the first frame of an image or video as 8-bit grayscale. Returns width,
+/// height and the row-major pixels.
+pub fn read_gray_image(path: &str) -> Result<(usize, usize, Vec<u8>), String> {
+    let (width, height, _) = get_video_info(path)?;
+
+    let output = Command::new("ffmpeg")
+        .args([
+            "-v",
+            "error",
+            "-i",
+            path,
+            "-frames:v",
+            "1",
+            "-f",
+            "rawvideo",
+            "-pix_fmt",
+            "gray",
+            "-",
+        ])
+        .output()
+        .map_err(|e| format!("ffmpeg failed to start: {}", e))?;
+
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(format!("ffmpeg failed to read '{}': {}", path, stderr));
+    }
+    if output.stdout.len() != width * height {
+        return Err(format!(
+            "Expected {} bytes of grayscale pixels from '{}', got {}",
+            width * height,
+            path,
+            output.stdout.len()
+        ));
+    }
+
+    Ok((width, height, output.stdout))
+}
+
 /// Get width, height and frame rate of a video.
 pub fn get_video_info(path: &str) -> Result<(usize, usize, f32), String> {
     let output = Command::new("ffprobe")
-        .args(&[
+        .args([
             "-v",
             "error",
             "-select_streams",
@@ -122,7 +160,7 @@ pub fn copy_streams_or_aac_transcode_audio(
     dest: &str,
 ) -> Result<(), String> {
     let status = Command::new("ffmpeg")
-        .args(&[
+        .args([
             "-v", "error", "-i", src_video, "-i", src_audio, "-c:v", "copy",
             "-c:a", "copy", "-map", "0:v:0", "-map", "1:a:0", "-n", dest,
         ])
@@ -131,7 +169,7 @@ pub fn copy_streams_or_aac_transcode_audio(
 
     if !status.success() {
         let output = Command::new("ffmpeg")
-            .args(&[
+            .args([
                 "-v", "error", "-i", src_video, "-i", src_audio, "-c:v",
                 "copy", "-c:a", "copy", "-map", "0:v:0", "-map", "1:a:0", "-n",
                 dest,
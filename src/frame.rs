@@ -1,3 +1,7 @@
+use std::str::FromStr;
+
+use crate::color::luma;
+
 #[derive(Debug, Clone)]
 pub struct Resolution {
     _width: isize,
@@ -72,6 +76,26 @@ impl Resolution {
     }
 }
 
+impl FromStr for Resolution {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let parts: Vec<&str> = s.split('x').collect();
+        if parts.len() != 2 {
+            return Err(format!("Expected WIDTHxHEIGHT, got '{}'", s));
+        }
+
+        let width = parts[0]
+            .parse::<isize>()
+            .map_err(|_| "Invalid width".to_string())?;
+        let height = parts[1]
+            .parse::<isize>()
+            .map_err(|_| "Invalid width".to_string())?;
+
+        Ok(Resolution::new(width, height))
+    }
+}
+
 fn round_even(n: f32) -> f32 {
     (n / 2.0).round() * 2.0
 }
@@ -93,35 +117,29 @@ impl<'a> Frame<'a> {
         assert!(height > -1);
         assert!(buffer.len() == (width * height * 3) as usize);
 
-        return Frame {
-            width: width,
-            height: height,
+        Frame {
+            width,
+            height,
             buffer,
-        };
+        }
     }
 
     pub fn get_rgb(&self, x: isize, y: isize) -> Option<RgbPixel> {
-        if let Some(i) = self.coordinate_to_index(x, y) {
+        self.coordinate_to_index(x, y).map(|i| {
             // This is safe since we already checked index validity; each pixel
             // occupies 3 slots in self.data, if i is safe, i+1 and i+2 is safe.
             unsafe {
-                Some((
+                (
                     *self.buffer.get_unchecked(i) as f32,
                     *self.buffer.get_unchecked(i + 1) as f32,
                     *self.buffer.get_unchecked(i + 2) as f32,
-                ))
+                )
             }
-        } else {
-            None
-        }
+        })
     }
 
     pub fn get_gray(&self, x: isize, y: isize) -> Option<GrayPixel> {
-        if let Some((r, g, b)) = self.get_rgb(x, y) {
-            Some(0.299 * r + 0.587 * g + 0.114 * b)
-        } else {
-            None
-        }
+        self.get_rgb(x, y).map(luma)
     }
 
     pub fn set_rgb(&mut self, x: isize, y: isize, new_rgb: RgbPixel) -> bool {
@@ -160,7 +178,77 @@ impl<'a> Frame<'a> {
     }
 }
 
+/// An owned f32 RGB matrix with the same layout as [Frame]. Unlike Frame,
+/// values are kept as is, so fractional and out of range values (e.g. diffused
+/// error) survive until written back with [FloatFrame::write_to].
+#[derive(Clone)]
+pub struct FloatFrame {
+    pub width: isize,
+    pub height: isize,
+    buffer: Vec<f32>,
+}
+
+impl FloatFrame {
+    pub fn from_frame(frame: &Frame) -> Self {
+        FloatFrame {
+            width: frame.width,
+            height: frame.height,
+            buffer: frame.buffer.iter().map(|&v| v as f32).collect(),
+        }
+    }
+
+    pub fn get_rgb(&self, x: isize, y: isize) -> Option<RgbPixel> {
+        self.coordinate_to_index(x, y)
+            .map(|i| (self.buffer[i], self.buffer[i + 1], self.buffer[i + 2]))
+    }
+
+    pub fn get_gray(&self, x: isize, y: isize) -> Option<GrayPixel> {
+        self.get_rgb(x, y).map(luma)
+    }
+
+    pub fn set_rgb(&mut self, x: isize, y: isize, new_rgb: RgbPixel) -> bool {
+        if let Some(i) = self.coordinate_to_index(x, y) {
+            let (r, g, b) = new_rgb;
+            self.buffer[i] = r;
+            self.buffer[i + 1] = g;
+            self.buffer[i + 2] = b;
+
+            true
+        } else {
+            false
+        }
+    }
+
+    pub fn set_gray(
+        &mut self,
+        x: isize,
+        y: isize,
+        new_gray: GrayPixel,
+    ) -> bool {
+        self.set_rgb(x, y, (new_gray, new_gray, new_gray))
+    }
+
+    /// Round and clamp every value to u8 and write it to frame, which must have
+    /// the same dimensions.
+    pub fn write_to(&self, frame: &mut Frame) {
+        assert!(self.width == frame.width && self.height == frame.height);
+
+        for (dest, &v) in frame.buffer.iter_mut().zip(self.buffer.iter()) {
+            *dest = v.round() as u8;
+        }
+    }
+
+    fn coordinate_to_index(&self, x: isize, y: isize) -> Option<usize> {
+        if 0 <= x && x < self.width && 0 <= y && y < self.height {
+            Some(((y * self.width + x) * 3) as usize)
+        } else {
+            None
+        }
+    }
+}
+
 #[cfg(test)]
+#[allow(clippy::useless_vec)]
 mod tests {
     use super::*;
 
@@ -263,4 +351,35 @@ mod tests {
 
         assert!(!frame.set_gray(-1, 0, 100.0));
     }
+
+    #[test]
+    fn float_frame_keeps_fractional_and_negative_values() {
+        let mut buf = [0u8; 3];
+        let frame = Frame::new(1, 1, &mut buf);
+        let mut float_frame = FloatFrame::from_frame(&frame);
+
+        float_frame.set_rgb(0, 0, (-12.25, 0.5, 300.75));
+        assert_eq!(float_frame.get_rgb(0, 0).unwrap(), (-12.25, 0.5, 300.75));
+    }
+
+    #[test]
+    fn float_frame_write_to_rounds_and_clamps() {
+        let mut buf = [0u8; 3];
+        let mut frame = Frame::new(1, 1, &mut buf);
+        let mut float_frame = FloatFrame::from_frame(&frame);
+
+        float_frame.set_rgb(0, 0, (-12.25, 127.5, 300.75));
+        float_frame.write_to(&mut frame);
+        assert_eq!(frame.get_rgb(0, 0).unwrap(), (0.0, 128.0, 255.0));
+    }
+
+    #[test]
+    fn float_frame_get_rgb_returns_none_on_out_of_bounds() {
+        let mut buf = [0u8; 3];
+        let frame = Frame::new(1, 1, &mut buf);
+        let float_frame = FloatFrame::from_frame(&frame);
+
+        assert_eq!(float_frame.get_rgb(1, 0), None);
+        assert_eq!(float_frame.get_rgb(0, -1), None);
+    }
 }
@@ -0,0 +1,184 @@
+use crate::frame::RgbPixel;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DotShape {
+    Round,
+    Ellipse,
+    Line,
+    Square,
+}
+
+impl DotShape {
+    /// Spot function of a point in a cell, with u and v in [-1, 1]. Dots grow
+    /// from the cell's center outwards in order of increasing spot value.
+    fn spot(&self, u: f32, v: f32) -> f32 {
+        match self {
+            DotShape::Round => u * u + v * v,
+            DotShape::Ellipse => u * u + (v / 0.7).powi(2),
+            DotShape::Line => v.abs(),
+            DotShape::Square => u.abs().max(v.abs()),
+        }
+    }
+}
+
+/// A clustered-dot (AM) halftone screen: a grid of cells rotated by an angle,
+/// each growing a dot whose area is proportional to ink coverage.
+#[derive(Debug, Clone)]
+pub struct Screen {
+    shape: DotShape,
+    cell_size: f32,
+    sin: f32,
+    cos: f32,
+    /// Spot values sampled evenly over a cell in ascending order, used to map a
+    /// spot value to the fraction of the cell's area below it.
+    samples: Vec<f32>,
+}
+
+impl Screen {
+    const SAMPLES_PER_SIDE: usize = 64;
+
+    /// Screen of cell_size pixel cells rotated by angle degrees.
+    pub fn new(shape: DotShape, cell_size: f32, angle: f32) -> Self {
+        assert!(cell_size > 0.0);
+
+        let n = Self::SAMPLES_PER_SIDE;
+        let mut samples = Vec::with_capacity(n * n);
+        for j in 0..n {
+            for i in 0..n {
+                let u = (i as f32 + 0.5) / n as f32 * 2.0 - 1.0;
+                let v = (j as f32 + 0.5) / n as f32 * 2.0 - 1.0;
+                samples.push(shape.spot(u, v));
+            }
+        }
+        samples.sort_by(f32::total_cmp);
+
+        let (sin, cos) = angle.to_radians().sin_cos();
+        Screen {
+            shape,
+            cell_size,
+            sin,
+            cos,
+            samples,
+        }
+    }
+
+    /// Whether the pixel at x, y is part of a dot when the screen is inked with
+    /// coverage in [0, 1].
+    pub fn is_inked(&self, x: isize, y: isize, coverage: f32) -> bool {
+        let (px, py) = (x as f32 + 0.5, y as f32 + 0.5);
+        let cu = (px * self.cos + py * self.sin) / self.cell_size;
+        let cv = (py * self.cos - px * self.sin) / self.cell_size;
+        let u = (cu - cu.floor()) * 2.0 - 1.0;
+        let v = (cv - cv.floor()) * 2.0 - 1.0;
+
+        let spot = self.shape.spot(u, v);
+        let below = self.samples.partition_point(|&s| s < spot);
+        (below as f32 + 0.5) / (self.samples.len() as f32) < coverage
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum Halftone {
+    /// A single black screen inked by the inverse of luma.
+    Gray(Screen),
+    /// Four process color screens, usually at different angles so their dots
+    /// form rosettes instead of moiré.
+    Cmyk {
+        cyan: Screen,
+        magenta: Screen,
+        yellow: Screen,
+        black: Screen,
+    },
+}
+
+/// Separate an RGB pixel in [0, 255] into CMYK coverages in [0, 1], with all
+/// the gray component moved to black.
+pub fn rgb_to_cmyk((r, g, b): RgbPixel) -> (f32, f32, f32, f32) {
+    let (r, g, b) = (r / 255.0, g / 255.0, b / 255.0);
+    let k = 1.0 - r.max(g).max(b);
+    if k >= 1.0 {
+        return (0.0, 0.0, 0.0, 1.0);
+    }
+
+    (
+        (1.0 - r - k) / (1.0 - k),
+        (1.0 - g - k) / (1.0 - k),
+        (1.0 - b - k) / (1.0 - k),
+        k,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SHAPES: [DotShape; 4] = [
+        DotShape::Round,
+        DotShape::Ellipse,
+        DotShape::Line,
+        DotShape::Square,
+    ];
+
+    fn inked_fraction(screen: &Screen, coverage: f32) -> f32 {
+        let size = 160;
+        let mut inked = 0;
+        for y in 0..size {
+            for x in 0..size {
+                if screen.is_inked(x, y, coverage) {
+                    inked += 1;
+                }
+            }
+        }
+        inked as f32 / (size * size) as f32
+    }
+
+    #[test]
+    fn inked_area_matches_coverage() {
+        // Dots can only grow by whole pixels, and unrotated lines by two whole
+        // rows at a time, which bounds how close they can get to coverage.
+        for shape in SHAPES {
+            for angle in [0.0, 15.0, 45.0] {
+                let screen = Screen::new(shape, 16.0, angle);
+                for coverage in [0.0, 0.1, 0.5, 0.8, 1.0] {
+                    let fraction = inked_fraction(&screen, coverage);
+                    assert!(
+                        (fraction - coverage).abs() < 0.07,
+                        "{:?} at {} degrees inked {} for {}",
+                        shape,
+                        angle,
+                        fraction,
+                        coverage
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn unrotated_screen_repeats_every_cell() {
+        let screen = Screen::new(DotShape::Round, 6.0, 0.0);
+        for y in 0..6 {
+            for x in 0..6 {
+                assert_eq!(
+                    screen.is_inked(x, y, 0.3),
+                    screen.is_inked(x + 6, y + 12, 0.3)
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn dots_grow_from_cell_center() {
+        let screen = Screen::new(DotShape::Round, 8.0, 0.0);
+        assert!(screen.is_inked(4, 4, 0.05));
+        assert!(!screen.is_inked(0, 0, 0.05));
+    }
+
+    #[test]
+    fn rgb_to_cmyk_separates_primaries() {
+        assert_eq!(rgb_to_cmyk((255.0, 255.0, 255.0)), (0.0, 0.0, 0.0, 0.0));
+        assert_eq!(rgb_to_cmyk((0.0, 0.0, 0.0)), (0.0, 0.0, 0.0, 1.0));
+        assert_eq!(rgb_to_cmyk((0.0, 255.0, 255.0)), (1.0, 0.0, 0.0, 0.0));
+        assert_eq!(rgb_to_cmyk((255.0, 255.0, 0.0)), (0.0, 0.0, 1.0, 0.0));
+    }
+}
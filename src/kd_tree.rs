@@ -0,0 +1,146 @@
+/// Ranges of at most this many nodes are searched linearly.
+const LEAF_SIZE: usize = 8;
+
+/// A 3-d tree for finding the nearest of a fixed set of points by euclidean
+/// distance, like the colors of a palette.
+#[derive(Debug, Clone, PartialEq)]
+pub struct KdTree {
+    /// Every range of nodes is split by its middle node, whose left are the
+    /// nodes below it on its axis and whose right are the rest.
+    nodes: Vec<Node>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Node {
+    point: [f32; 3],
+    /// Index of the point in the points the tree was built from.
+    index: usize,
+    axis: usize,
+}
+
+impl KdTree {
+    pub fn new(points: &[(f32, f32, f32)]) -> Self {
+        let mut nodes: Vec<Node> = points
+            .iter()
+            .enumerate()
+            .map(|(index, &(x, y, z))| Node {
+                point: [x, y, z],
+                index,
+                axis: 0,
+            })
+            .collect();
+        build(&mut nodes);
+        KdTree { nodes }
+    }
+
+    /// Index of the point nearest to target, the lowest one of equally near
+    /// points. Panics if the tree is empty.
+    pub fn nearest(&self, (x, y, z): (f32, f32, f32)) -> usize {
+        let mut best = (f32::INFINITY, usize::MAX);
+        search(&self.nodes, [x, y, z], &mut best);
+        best.1
+    }
+}
+
+/// Split nodes on the axis they spread the most along, at the median.
+fn build(nodes: &mut [Node]) {
+    if nodes.len() <= 1 {
+        return;
+    }
+
+    let spread = |axis: usize| {
+        let values = nodes.iter().map(|n| n.point[axis]);
+        let max = values.clone().fold(f32::NEG_INFINITY, f32::max);
+        let min = values.fold(f32::INFINITY, f32::min);
+        max - min
+    };
+    let axis = (0..3)
+        .max_by(|&a, &b| spread(a).total_cmp(&spread(b)))
+        .unwrap();
+    let mid = nodes.len() / 2;
+    nodes.select_nth_unstable_by(mid, |a, b| {
+        a.point[axis].total_cmp(&b.point[axis])
+    });
+    nodes[mid].axis = axis;
+
+    build(&mut nodes[..mid]);
+    build(&mut nodes[mid + 1..]);
+}
+
+fn search(nodes: &[Node], target: [f32; 3], best: &mut (f32, usize)) {
+    let mut consider = |node: &Node| {
+        let distance = (node.point[0] - target[0]).powi(2)
+            + (node.point[1] - target[1]).powi(2)
+            + (node.point[2] - target[2]).powi(2);
+        if (distance, node.index) < *best {
+            *best = (distance, node.index);
+        }
+    };
+    // Checking every node of small ranges is faster than descending further.
+    if nodes.len() <= LEAF_SIZE {
+        nodes.iter().for_each(consider);
+        return;
+    }
+
+    let mid = nodes.len() / 2;
+    let node = &nodes[mid];
+    consider(node);
+
+    let offset = target[node.axis] - node.point[node.axis];
+    let (near, far) = if offset < 0.0 {
+        (&nodes[..mid], &nodes[mid + 1..])
+    } else {
+        (&nodes[mid + 1..], &nodes[..mid])
+    };
+    search(near, target, best);
+    // Equally near points on the far side may have a lower index.
+    if offset * offset <= best.0 {
+        search(far, target, best);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    #[test]
+    fn nearest_matches_linear_search() {
+        let mut rng = StdRng::seed_from_u64(0);
+        let mut random_point = || {
+            (
+                rng.random::<f32>(),
+                rng.random::<f32>(),
+                rng.random::<f32>(),
+            )
+        };
+        let points: Vec<(f32, f32, f32)> =
+            (0..100).map(|_| random_point()).collect();
+        let tree = KdTree::new(&points);
+
+        for _ in 0..1000 {
+            let target = random_point();
+            let distance = |&(x, y, z): &(f32, f32, f32)| {
+                (x - target.0).powi(2)
+                    + (y - target.1).powi(2)
+                    + (z - target.2).powi(2)
+            };
+            let expected = (0..points.len())
+                .min_by(|&a, &b| {
+                    distance(&points[a]).total_cmp(&distance(&points[b]))
+                })
+                .unwrap();
+            assert_eq!(tree.nearest(target), expected);
+        }
+    }
+
+    #[test]
+    fn nearest_prefers_lowest_index_of_duplicates() {
+        let points = [(1.0, 1.0, 1.0), (0.0, 0.0, 0.0), (1.0, 1.0, 1.0)];
+        let tree = KdTree::new(&points);
+        assert_eq!(tree.nearest((0.9, 0.9, 0.9)), 0);
+        assert_eq!(tree.nearest((0.1, 0.0, 0.0)), 1);
+    }
+}
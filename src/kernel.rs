@@ -0,0 +1,592 @@
+use std::fs;
+
+/// An error diffusion kernel. Each weight is an offset relative to the pixel
+/// being quantized, which receives weight / divisor of that pixel's error.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DiffusionKernel {
+    pub weights: Vec<((isize, isize), f32)>,
+    pub divisor: f32,
+}
+
+impl DiffusionKernel {
+    pub fn new(weights: &[((isize, isize), f32)], divisor: f32) -> Self {
+        DiffusionKernel {
+            weights: weights.to_vec(),
+            divisor,
+        }
+    }
+
+    /// Weights already divided by the divisor.
+    pub fn coefficients(&self) -> Vec<((isize, isize), f32)> {
+        self.weights
+            .iter()
+            .map(|&(offset, weight)| (offset, weight / self.divisor))
+            .collect()
+    }
+
+    /// Parse a kernel written as rows separated by '/' or newlines, followed by
+    /// the divisor. The first row starts with '*', the pixel being quantized,
+    /// followed by the weights to its right, since error can't go back to
+    /// pixels already quantized. Every row below must have an odd
+    /// number of weights and is centered under '*'. Floyd-Steinberg is written
+    /// as "* 7 / 3 5 1 / 16". Anything after '#' on a line is ignored.
+    pub fn parse(s: &str) -> Result<Self, String> {
+        let segments: Vec<Vec<&str>> = s
+            .lines()
+            .map(|line| line.split('#').next().unwrap())
+            .flat_map(|line| line.split('/'))
+            .map(|segment| segment.split_whitespace().collect::<Vec<&str>>())
+            .filter(|segment| !segment.is_empty())
+            .collect();
+
+        let (divisor_segment, rows) = match segments.split_last() {
+            Some((divisor, rows)) if !rows.is_empty() => (divisor, rows),
+            _ => return Err("Expected at least one row and a divisor".into()),
+        };
+        let divisor = match divisor_segment.as_slice() {
+            [divisor] => parse_weight(divisor)?,
+            _ => return Err("Expected divisor as the last segment".into()),
+        };
+        if divisor <= 0.0 {
+            return Err("Divisor must be positive".into());
+        }
+
+        let mut weights = Vec::new();
+        for (dy, row) in rows.iter().enumerate() {
+            if dy == 0 {
+                if row[0] != "*" {
+                    return Err("First row must start with '*'".into());
+                }
+                for (dx, weight) in row.iter().enumerate().skip(1) {
+                    weights.push(((dx as isize, 0), parse_weight(weight)?));
+                }
+            } else {
+                if row.len() % 2 == 0 {
+                    return Err(format!(
+                        "Row {} must have an odd number of weights",
+                        dy + 1
+                    ));
+                }
+                let half = (row.len() / 2) as isize;
+                for (i, weight) in row.iter().enumerate() {
+                    let offset = (i as isize - half, dy as isize);
+                    weights.push((offset, parse_weight(weight)?));
+                }
+            }
+        }
+        weights.retain(|&(_, weight)| weight != 0.0);
+
+        let sum: f32 = weights.iter().map(|&(_, weight)| weight).sum();
+        if weights.is_empty() {
+            return Err("Kernel must have at least one non-zero weight".into());
+        }
+        if sum > divisor {
+            return Err(format!(
+                "Weights sum to {} which exceeds divisor {}, error would grow \
+                 without bound",
+                sum, divisor
+            ));
+        }
+
+        Ok(DiffusionKernel { weights, divisor })
+    }
+
+    /// Read and [DiffusionKernel::parse] a kernel file.
+    pub fn load(path: &str) -> Result<Self, String> {
+        let contents = fs::read_to_string(path)
+            .map_err(|e| format!("Reading kernel '{}' failed: {}", path, e))?;
+        Self::parse(&contents)
+            .map_err(|e| format!("Invalid kernel '{}': {}", path, e))
+    }
+
+    pub fn floyd_steinberg() -> Self {
+        Self::new(&FLOYD_STEINBERG, 16.0)
+    }
+
+    /// Only diffuses 6/8 of the error, which keeps contrast high at the cost of
+    /// detail in highlights and shadows.
+    pub fn atkinson() -> Self {
+        Self::new(&ATKINSON, 8.0)
+    }
+
+    pub fn jarvis_judice_ninke() -> Self {
+        Self::new(&JARVIS_JUDICE_NINKE, 48.0)
+    }
+
+    pub fn stucki() -> Self {
+        Self::new(&STUCKI, 42.0)
+    }
+
+    pub fn burkes() -> Self {
+        Self::new(&BURKES, 32.0)
+    }
+
+    pub fn sierra() -> Self {
+        Self::new(&SIERRA, 32.0)
+    }
+
+    pub fn two_row_sierra() -> Self {
+        Self::new(&TWO_ROW_SIERRA, 16.0)
+    }
+
+    pub fn sierra_lite() -> Self {
+        Self::new(&SIERRA_LITE, 4.0)
+    }
+}
+
+/// Error diffusion whose coefficients depend on the intensity of the pixel
+/// being quantized. Error only goes to the right, below left and below
+/// neighbors, relative to scan direction.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum VariableKernel {
+    /// Ostromoukhov's coefficients, optimized for a blue-noise spectrum at
+    /// every intensity.
+    Ostromoukhov,
+    /// Zhou and Fang's coefficients, with a random threshold modulation that
+    /// breaks up the remaining mid-tone patterns.
+    ZhouFang,
+}
+
+/// Coefficients for one intensity, summing to 1.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct VariableCoefficients {
+    pub right: f32,
+    pub below_left: f32,
+    pub below: f32,
+    /// Strength in [0, 1] of the random threshold modulation.
+    pub modulation: f32,
+}
+
+impl VariableKernel {
+    /// Coefficients of every intensity in [0, 255].
+    pub fn table(&self) -> Vec<VariableCoefficients> {
+        (0..256)
+            .map(|intensity| {
+                // Both tables are symmetric around the middle intensity.
+                let level = if intensity > 127 {
+                    255 - intensity
+                } else {
+                    intensity
+                };
+                match self {
+                    VariableKernel::Ostromoukhov => {
+                        let (r, dl, d, sum) = OSTROMOUKHOV[level];
+                        let sum = sum as f32;
+                        VariableCoefficients {
+                            right: r as f32 / sum,
+                            below_left: dl as f32 / sum,
+                            below: d as f32 / sum,
+                            modulation: 0.0,
+                        }
+                    }
+                    VariableKernel::ZhouFang => zhou_fang(level),
+                }
+            })
+            .collect()
+    }
+}
+
+/// Zhou-Fang coefficients and modulation strength, linearly interpolated
+/// between the key levels given in the paper.
+fn zhou_fang(level: usize) -> VariableCoefficients {
+    let normalized = |(r, dl, d): (f32, f32, f32)| {
+        let sum = r + dl + d;
+        (r / sum, dl / sum, d / sum)
+    };
+    let interpolate = |a: f32, b: f32, t: f32| a + (b - a) * t;
+
+    let i = ZHOU_FANG_COEFFICIENTS
+        .windows(2)
+        .position(|w| level < w[1].0)
+        .unwrap_or(ZHOU_FANG_COEFFICIENTS.len() - 2);
+    let (lo, lo_coef) = ZHOU_FANG_COEFFICIENTS[i];
+    let (hi, hi_coef) = ZHOU_FANG_COEFFICIENTS[i + 1];
+    let t = (level - lo) as f32 / (hi - lo) as f32;
+    let (lo_r, lo_dl, lo_d) = normalized(lo_coef);
+    let (hi_r, hi_dl, hi_d) = normalized(hi_coef);
+
+    let j = ZHOU_FANG_MODULATION
+        .windows(2)
+        .position(|w| level < w[1].0)
+        .unwrap_or(ZHOU_FANG_MODULATION.len() - 2);
+    let (lo_level, lo_mod) = ZHOU_FANG_MODULATION[j];
+    let (hi_level, hi_mod) = ZHOU_FANG_MODULATION[j + 1];
+    let m = (level - lo_level) as f32 / (hi_level - lo_level) as f32;
+
+    VariableCoefficients {
+        right: interpolate(lo_r, hi_r, t),
+        below_left: interpolate(lo_dl, hi_dl, t),
+        below: interpolate(lo_d, hi_d, t),
+        modulation: interpolate(lo_mod, hi_mod, m),
+    }
+}
+
+fn parse_weight(s: &str) -> Result<f32, String> {
+    match s.parse::<f32>() {
+        Ok(weight) if weight.is_finite() && weight >= 0.0 => Ok(weight),
+        _ => Err(format!("Invalid weight '{}'", s)),
+    }
+}
+
+const FLOYD_STEINBERG: [((isize, isize), f32); 4] =
+    [((1, 0), 7.0), ((-1, 1), 3.0), ((0, 1), 5.0), ((1, 1), 1.0)];
+
+const ATKINSON: [((isize, isize), f32); 6] = [
+    ((1, 0), 1.0),
+    ((2, 0), 1.0),
+    ((-1, 1), 1.0),
+    ((0, 1), 1.0),
+    ((1, 1), 1.0),
+    ((0, 2), 1.0),
+];
+
+const JARVIS_JUDICE_NINKE: [((isize, isize), f32); 12] = [
+    ((1, 0), 7.0),
+    ((2, 0), 5.0),
+    ((-2, 1), 3.0),
+    ((-1, 1), 5.0),
+    ((0, 1), 7.0),
+    ((1, 1), 5.0),
+    ((2, 1), 3.0),
+    ((-2, 2), 1.0),
+    ((-1, 2), 3.0),
+    ((0, 2), 5.0),
+    ((1, 2), 3.0),
+    ((2, 2), 1.0),
+];
+
+const STUCKI: [((isize, isize), f32); 12] = [
+    ((1, 0), 8.0),
+    ((2, 0), 4.0),
+    ((-2, 1), 2.0),
+    ((-1, 1), 4.0),
+    ((0, 1), 8.0),
+    ((1, 1), 4.0),
+    ((2, 1), 2.0),
+    ((-2, 2), 1.0),
+    ((-1, 2), 2.0),
+    ((0, 2), 4.0),
+    ((1, 2), 2.0),
+    ((2, 2), 1.0),
+];
+
+const BURKES: [((isize, isize), f32); 7] = [
+    ((1, 0), 8.0),
+    ((2, 0), 4.0),
+    ((-2, 1), 2.0),
+    ((-1, 1), 4.0),
+    ((0, 1), 8.0),
+    ((1, 1), 4.0),
+    ((2, 1), 2.0),
+];
+
+const SIERRA: [((isize, isize), f32); 10] = [
+    ((1, 0), 5.0),
+    ((2, 0), 3.0),
+    ((-2, 1), 2.0),
+    ((-1, 1), 4.0),
+    ((0, 1), 5.0),
+    ((1, 1), 4.0),
+    ((2, 1), 2.0),
+    ((-1, 2), 2.0),
+    ((0, 2), 3.0),
+    ((1, 2), 2.0),
+];
+
+const TWO_ROW_SIERRA: [((isize, isize), f32); 7] = [
+    ((1, 0), 4.0),
+    ((2, 0), 3.0),
+    ((-2, 1), 1.0),
+    ((-1, 1), 2.0),
+    ((0, 1), 3.0),
+    ((1, 1), 2.0),
+    ((2, 1), 1.0),
+];
+
+const SIERRA_LITE: [((isize, isize), f32); 3] =
+    [((1, 0), 2.0), ((-1, 1), 1.0), ((0, 1), 1.0)];
+
+/// (right, below left, below, sum) for intensities [0, 127] from "A Simple and
+/// Efficient Error-Diffusion Algorithm", Ostromoukhov 2001.
+const OSTROMOUKHOV: [(u32, u32, u32, u32); 128] = [
+    (13, 0, 5, 18),
+    (13, 0, 5, 18),
+    (21, 0, 10, 31),
+    (7, 0, 4, 11),
+    (8, 0, 5, 13),
+    (47, 3, 28, 78),
+    (23, 3, 13, 39),
+    (15, 3, 8, 26),
+    (22, 6, 11, 39),
+    (43, 15, 20, 78),
+    (7, 3, 3, 13),
+    (501, 224, 211, 936),
+    (249, 116, 103, 468),
+    (165, 80, 67, 312),
+    (123, 62, 49, 234),
+    (489, 256, 191, 936),
+    (81, 44, 31, 156),
+    (483, 272, 181, 936),
+    (60, 35, 22, 117),
+    (53, 32, 19, 104),
+    (237, 148, 83, 468),
+    (471, 304, 161, 936),
+    (3, 2, 1, 6),
+    (459, 304, 161, 924),
+    (38, 25, 14, 77),
+    (453, 296, 175, 924),
+    (225, 146, 91, 462),
+    (149, 96, 63, 308),
+    (111, 71, 49, 231),
+    (63, 40, 29, 132),
+    (73, 46, 35, 154),
+    (435, 272, 217, 924),
+    (108, 67, 56, 231),
+    (13, 8, 7, 28),
+    (213, 130, 119, 462),
+    (423, 256, 245, 924),
+    (5, 3, 3, 11),
+    (281, 173, 162, 616),
+    (141, 89, 78, 308),
+    (283, 183, 150, 616),
+    (71, 47, 36, 154),
+    (285, 193, 138, 616),
+    (13, 9, 6, 28),
+    (41, 29, 18, 88),
+    (36, 26, 15, 77),
+    (289, 213, 114, 616),
+    (145, 109, 54, 308),
+    (291, 223, 102, 616),
+    (73, 57, 24, 154),
+    (293, 233, 90, 616),
+    (21, 17, 6, 44),
+    (295, 243, 78, 616),
+    (37, 31, 9, 77),
+    (27, 23, 6, 56),
+    (149, 129, 30, 308),
+    (299, 263, 54, 616),
+    (75, 67, 12, 154),
+    (43, 39, 6, 88),
+    (151, 139, 18, 308),
+    (303, 283, 30, 616),
+    (38, 36, 3, 77),
+    (305, 293, 18, 616),
+    (153, 149, 6, 308),
+    (307, 303, 6, 616),
+    (1, 1, 0, 2),
+    (101, 105, 2, 208),
+    (49, 53, 2, 104),
+    (95, 107, 6, 208),
+    (23, 27, 2, 52),
+    (89, 109, 10, 208),
+    (43, 55, 6, 104),
+    (83, 111, 14, 208),
+    (5, 7, 1, 13),
+    (172, 181, 37, 390),
+    (97, 76, 22, 195),
+    (72, 41, 17, 130),
+    (119, 47, 29, 195),
+    (4, 1, 1, 6),
+    (4, 1, 1, 6),
+    (65, 18, 17, 100),
+    (95, 29, 26, 150),
+    (185, 62, 53, 300),
+    (30, 11, 9, 50),
+    (35, 14, 11, 60),
+    (85, 37, 28, 150),
+    (55, 26, 19, 100),
+    (80, 41, 29, 150),
+    (155, 86, 59, 300),
+    (5, 3, 2, 10),
+    (5, 3, 2, 10),
+    (5, 3, 2, 10),
+    (5, 3, 2, 10),
+    (5, 3, 2, 10),
+    (5, 3, 2, 10),
+    (5, 3, 2, 10),
+    (5, 3, 2, 10),
+    (5, 3, 2, 10),
+    (5, 3, 2, 10),
+    (5, 3, 2, 10),
+    (5, 3, 2, 10),
+    (5, 3, 2, 10),
+    (5, 3, 2, 10),
+    (5, 3, 2, 10),
+    (5, 3, 2, 10),
+    (5, 3, 2, 10),
+    (5, 3, 2, 10),
+    (5, 3, 2, 10),
+    (5, 3, 2, 10),
+    (305, 176, 119, 600),
+    (155, 86, 59, 300),
+    (105, 56, 39, 200),
+    (80, 41, 29, 150),
+    (65, 32, 23, 120),
+    (55, 26, 19, 100),
+    (335, 152, 113, 600),
+    (85, 37, 28, 150),
+    (115, 48, 37, 200),
+    (35, 14, 11, 60),
+    (355, 136, 109, 600),
+    (30, 11, 9, 50),
+    (365, 128, 107, 600),
+    (185, 62, 53, 300),
+    (25, 8, 7, 40),
+    (95, 29, 26, 150),
+    (385, 112, 103, 600),
+    (65, 18, 17, 100),
+    (395, 104, 101, 600),
+    (4, 1, 1, 6),
+];
+
+/// (level, (right, below left, below)) key levels from "Improving Mid-tone
+/// Quality of Variable-Coefficient Error Diffusion Using Threshold
+/// Modulation", Zhou and Fang 2003.
+const ZHOU_FANG_COEFFICIENTS: [(usize, (f32, f32, f32)); 18] = [
+    (0, (13.0, 0.0, 5.0)),
+    (1, (1300249.0, 0.0, 499250.0)),
+    (2, (213113.0, 287.0, 99357.0)),
+    (3, (351854.0, 0.0, 199965.0)),
+    (4, (801100.0, 0.0, 490999.0)),
+    (10, (704075.0, 297466.0, 303694.0)),
+    (22, (46613.0, 31917.0, 21469.0)),
+    (32, (47482.0, 30617.0, 21900.0)),
+    (44, (43024.0, 42131.0, 14826.0)),
+    (64, (36411.0, 43219.0, 20369.0)),
+    (72, (38477.0, 53843.0, 7678.0)),
+    (77, (40503.0, 51547.0, 7948.0)),
+    (85, (35865.0, 34108.0, 30026.0)),
+    (95, (34117.0, 36899.0, 28983.0)),
+    (102, (35464.0, 35049.0, 29485.0)),
+    (107, (16477.0, 18810.0, 14712.0)),
+    (112, (33360.0, 37954.0, 28685.0)),
+    (127, (35269.0, 36066.0, 28664.0)),
+];
+
+/// (level, strength) key levels of the Zhou-Fang threshold modulation.
+const ZHOU_FANG_MODULATION: [(usize, f32); 9] = [
+    (0, 0.0),
+    (44, 0.34),
+    (64, 0.5),
+    (85, 1.0),
+    (95, 0.17),
+    (102, 0.5),
+    (107, 0.7),
+    (112, 0.79),
+    (127, 1.0),
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn named_kernels() -> Vec<DiffusionKernel> {
+        vec![
+            DiffusionKernel::floyd_steinberg(),
+            DiffusionKernel::jarvis_judice_ninke(),
+            DiffusionKernel::stucki(),
+            DiffusionKernel::burkes(),
+            DiffusionKernel::sierra(),
+            DiffusionKernel::two_row_sierra(),
+            DiffusionKernel::sierra_lite(),
+        ]
+    }
+
+    #[test]
+    fn named_kernels_diffuse_all_error() {
+        for kernel in named_kernels() {
+            let sum: f32 = kernel.weights.iter().map(|&(_, w)| w).sum();
+            assert_eq!(sum, kernel.divisor, "{:?}", kernel);
+        }
+    }
+
+    #[test]
+    fn atkinson_diffuses_three_quarters_of_error() {
+        let sum: f32 = DiffusionKernel::atkinson()
+            .coefficients()
+            .iter()
+            .map(|&(_, c)| c)
+            .sum();
+        assert_eq!(sum, 0.75);
+    }
+
+    #[test]
+    fn named_kernels_only_reach_unvisited_pixels() {
+        for kernel in named_kernels() {
+            for &((dx, dy), _) in &kernel.weights {
+                assert!(dy > 0 || (dy == 0 && dx > 0), "{:?}", kernel);
+            }
+        }
+    }
+
+    #[test]
+    fn parse_floyd_steinberg() {
+        let kernel = DiffusionKernel::parse("* 7 / 3 5 1 / 16").unwrap();
+        assert_eq!(kernel, DiffusionKernel::floyd_steinberg());
+    }
+
+    #[test]
+    fn parse_multiline_with_comments() {
+        let kernel = DiffusionKernel::parse(
+            "# Jarvis-Judice-Ninke\n* 7 5\n3 5 7 5 3\n1 3 5 3 1\n\n48\n",
+        )
+        .unwrap();
+        assert_eq!(kernel, DiffusionKernel::jarvis_judice_ninke());
+    }
+
+    #[test]
+    fn parse_drops_zero_weights() {
+        let kernel = DiffusionKernel::parse("* 2 / 1 1 0 / 4").unwrap();
+        assert_eq!(kernel, DiffusionKernel::sierra_lite());
+    }
+
+    #[test]
+    fn parse_rejects_invalid_kernels() {
+        let invalid = [
+            "",
+            "16",
+            "7 * / 3 5 1 / 16",
+            "* 7 / 3 5 / 16",
+            "* 7 / 3 5 1",
+            "* 7 / 3 5 1 / 0",
+            "* 7 / 3 -5 1 / 16",
+            "* 7 / 3 x 1 / 16",
+            "* 0 / 0 / 16",
+            "* 9 / 3 5 1 / 16",
+        ];
+
+        for s in invalid {
+            assert!(DiffusionKernel::parse(s).is_err(), "{:?}", s);
+        }
+    }
+
+    #[test]
+    fn ostromoukhov_rows_sum_to_their_divisor() {
+        for (i, &(r, dl, d, sum)) in OSTROMOUKHOV.iter().enumerate() {
+            assert_eq!(r + dl + d, sum, "row {}", i);
+        }
+    }
+
+    #[test]
+    fn variable_tables_are_normalized_and_symmetric() {
+        for kernel in [VariableKernel::Ostromoukhov, VariableKernel::ZhouFang] {
+            let table = kernel.table();
+            assert_eq!(table.len(), 256);
+
+            for (i, c) in table.iter().enumerate() {
+                let sum = c.right + c.below_left + c.below;
+                assert!((sum - 1.0).abs() < 1e-5, "{:?} {}", kernel, i);
+                assert!((0.0..=1.0).contains(&c.modulation));
+                assert_eq!(*c, table[255 - i]);
+            }
+        }
+    }
+
+    #[test]
+    fn zhou_fang_hits_key_levels_exactly() {
+        let table = VariableKernel::ZhouFang.table();
+        assert_eq!(table[85].modulation, 1.0);
+        assert_eq!(table[95].modulation, 0.17);
+        assert!((table[0].right - 13.0 / 18.0).abs() < 1e-6);
+    }
+}
@@ -0,0 +1,273 @@
+use crate::color::decode_srgb;
+use crate::frame::RgbPixel;
+
+/// Output levels of each RGB channel, which don't have to be evenly spaced or
+/// the same for every channel, like the 3, 3 and 2 bits of RGB332.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ChannelLevels {
+    /// Ascending levels of red, green and blue.
+    channels: [Vec<f32>; 3],
+}
+
+impl ChannelLevels {
+    /// Levels in [0, 255] of red, green and blue, in any order.
+    pub fn new(channels: [Vec<f32>; 3]) -> Result<Self, String> {
+        let channels = channels.map(|mut levels| {
+            levels.sort_by(f32::total_cmp);
+            levels.dedup();
+            levels
+        });
+        if channels.iter().any(|levels| levels.is_empty()) {
+            return Err("Every channel must have at least one level".into());
+        }
+        if channels
+            .iter()
+            .flatten()
+            .any(|l| !(0.0..=255.0).contains(l))
+        {
+            return Err("Levels must be in [0, 255]".into());
+        }
+        Ok(ChannelLevels { channels })
+    }
+
+    /// count evenly spaced levels of every channel.
+    pub fn uniform(count: u32) -> Self {
+        Self::per_channel([count; 3])
+    }
+
+    /// Evenly spaced levels, counts of them for red, green and blue.
+    pub fn per_channel(counts: [u32; 3]) -> Self {
+        ChannelLevels {
+            channels: counts.map(|count| {
+                assert!(count >= 2);
+                let gap = 255.0 / (count as f32 - 1.0);
+                (0..count).map(|i| i as f32 * gap).collect()
+            }),
+        }
+    }
+
+    /// Parse levels given as bits per channel like "rgb332" or "rgb565", level
+    /// counts per channel like "6x7x6", or level tables like "0,96,255" for
+    /// every channel or "0,255/0,128,255/0,255" for red, green and blue.
+    pub fn parse(s: &str) -> Result<Self, String> {
+        let invalid = |e: String| format!("Invalid levels '{}': {}", s, e);
+
+        if let Some(bits) = s.strip_prefix("rgb") {
+            let bits: Vec<u32> = bits
+                .chars()
+                .map(|c| c.to_digit(10))
+                .collect::<Option<_>>()
+                .unwrap_or_default();
+            if bits.len() != 3 || bits.iter().any(|&b| !(1..=8).contains(&b)) {
+                return Err(invalid("Expected 1 to 8 bits per channel".into()));
+            }
+            return Ok(Self::per_channel(
+                [bits[0], bits[1], bits[2]].map(|b| 1 << b),
+            ));
+        }
+
+        if s.contains('x') {
+            let counts = s
+                .split('x')
+                .map(|count| match count.parse::<u32>() {
+                    Ok(count) if (2..=256).contains(&count) => Ok(count),
+                    _ => {
+                        Err(invalid(format!("Invalid level count '{}'", count)))
+                    }
+                })
+                .collect::<Result<Vec<u32>, String>>()?;
+            return match counts[..] {
+                [r, g, b] => Ok(Self::per_channel([r, g, b])),
+                _ => Err(invalid("Expected RxGxB level counts".into())),
+            };
+        }
+
+        let tables = s
+            .split('/')
+            .map(|table| {
+                table
+                    .split(',')
+                    .map(|level| {
+                        level.trim().parse::<f32>().map_err(|_| {
+                            invalid(format!("Invalid level '{}'", level))
+                        })
+                    })
+                    .collect::<Result<Vec<f32>, String>>()
+            })
+            .collect::<Result<Vec<Vec<f32>>, String>>()?;
+        let channels = match &tables[..] {
+            [all] => [all.clone(), all.clone(), all.clone()],
+            [r, g, b] => [r.clone(), g.clone(), b.clone()],
+            _ => {
+                return Err(invalid(
+                    "Expected one level table or one per channel".into(),
+                ));
+            }
+        };
+        Self::new(channels).map_err(invalid)
+    }
+
+    /// Ascending levels of channel 0, 1 or 2 for red, green and blue.
+    pub fn channel(&self, channel: usize) -> &[f32] {
+        &self.channels[channel]
+    }
+
+    /// The level of channel closest to value.
+    pub fn nearest(&self, channel: usize, value: f32) -> f32 {
+        let levels = &self.channels[channel];
+        let above = levels.partition_point(|&l| l < value);
+        match (above.checked_sub(1), levels.get(above)) {
+            (Some(below), Some(&level))
+                if level - value < value - levels[below] =>
+            {
+                level
+            }
+            (Some(below), _) => levels[below],
+            (None, _) => levels[0],
+        }
+    }
+
+    /// Nearest levels of every channel of rgb.
+    pub fn quantize(&self, (r, g, b): RgbPixel) -> RgbPixel {
+        (self.nearest(0, r), self.nearest(1, g), self.nearest(2, b))
+    }
+
+    /// Fractional index of value among the levels of channel, like 1.25 a
+    /// quarter of the way from the second level to the third, clamped to the
+    /// first and last level.
+    pub fn position(&self, channel: usize, value: f32) -> f32 {
+        let levels = &self.channels[channel];
+        match levels.partition_point(|&l| l <= value) {
+            0 => 0.0,
+            above if above == levels.len() => (above - 1) as f32,
+            above => {
+                let (below, level) = (levels[above - 1], levels[above]);
+                (above - 1) as f32 + (value - below) / (level - below)
+            }
+        }
+    }
+
+    /// The level of channel value rounds to when it rounds up wherever its
+    /// fraction of the way between the levels around it exceeds threshold.
+    /// Thresholds outside [0, 1) reach levels further away.
+    pub fn threshold(&self, channel: usize, value: f32, threshold: f32) -> f32 {
+        let levels = &self.channels[channel];
+        let index = (self.position(channel, value) - threshold).ceil();
+        levels[(index.max(0.0) as usize).min(levels.len() - 1)]
+    }
+
+    /// The levels red has that every other channel has too, as the levels of
+    /// every channel. Gray outputs can only use these. Levels less than half
+    /// apart count as the same, as they are output as the same byte.
+    pub fn shared(&self) -> Result<Self, String> {
+        let [red, others @ ..] = &self.channels;
+        let shared: Vec<f32> = red
+            .iter()
+            .copied()
+            .filter(|&l| {
+                others
+                    .iter()
+                    .all(|levels| levels.iter().any(|&o| (l - o).abs() < 0.5))
+            })
+            .collect();
+        if shared.is_empty() {
+            return Err(
+                "Gray mode needs a level shared by every channel".into()
+            );
+        }
+        Ok(ChannelLevels {
+            channels: [shared.clone(), shared.clone(), shared],
+        })
+    }
+
+    /// The same levels in linear light.
+    pub fn linearized(&self) -> Self {
+        ChannelLevels {
+            channels: self.channels.clone().map(|levels| {
+                levels
+                    .into_iter()
+                    .map(|l| decode_srgb((l, l, l)).0)
+                    .collect()
+            }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_reads_bits_per_channel() {
+        let levels = ChannelLevels::parse("rgb565").unwrap();
+        assert_eq!(levels.channel(0).len(), 32);
+        assert_eq!(levels.channel(1).len(), 64);
+        assert_eq!(levels.channel(2).len(), 32);
+        assert_eq!(
+            ChannelLevels::parse("rgb332").unwrap().channel(2),
+            &[0.0, 85.0, 170.0, 255.0]
+        );
+        assert!(ChannelLevels::parse("rgb39").is_err());
+        assert!(ChannelLevels::parse("rgb3x3").is_err());
+    }
+
+    #[test]
+    fn parse_reads_counts_per_channel() {
+        let levels = ChannelLevels::parse("6x7x6").unwrap();
+        assert_eq!(levels, ChannelLevels::per_channel([6, 7, 6]));
+        assert_eq!(levels.channel(1)[1], 42.5);
+        assert!(ChannelLevels::parse("6x7").is_err());
+        assert!(ChannelLevels::parse("6x1x6").is_err());
+    }
+
+    #[test]
+    fn parse_reads_level_tables() {
+        let levels = ChannelLevels::parse("255,0,96").unwrap();
+        assert_eq!(levels.channel(0), &[0.0, 96.0, 255.0]);
+        assert_eq!(levels.channel(2), &[0.0, 96.0, 255.0]);
+
+        let levels = ChannelLevels::parse("0,255/0,128,255/0").unwrap();
+        assert_eq!(levels.channel(1), &[0.0, 128.0, 255.0]);
+        assert_eq!(levels.channel(2), &[0.0]);
+
+        assert!(ChannelLevels::parse("0,300").is_err());
+        assert!(ChannelLevels::parse("0,a").is_err());
+        assert!(ChannelLevels::parse("0/255").is_err());
+    }
+
+    #[test]
+    fn nearest_picks_closest_level() {
+        let levels = ChannelLevels::parse("0,96,255").unwrap();
+        assert_eq!(levels.nearest(0, -10.0), 0.0);
+        assert_eq!(levels.nearest(0, 40.0), 0.0);
+        assert_eq!(levels.nearest(0, 60.0), 96.0);
+        assert_eq!(levels.nearest(0, 180.0), 255.0);
+        assert_eq!(levels.nearest(0, 300.0), 255.0);
+    }
+
+    #[test]
+    fn threshold_compares_against_the_interval_around_value() {
+        let levels = ChannelLevels::parse("0,96,255").unwrap();
+        assert_eq!(levels.position(0, 48.0), 0.5);
+        assert_eq!(levels.position(0, 175.5), 1.5);
+        assert_eq!(levels.position(0, 300.0), 2.0);
+
+        assert_eq!(levels.threshold(0, 48.0, 0.4), 96.0);
+        assert_eq!(levels.threshold(0, 48.0, 0.6), 0.0);
+        assert_eq!(levels.threshold(0, 175.5, 0.4), 255.0);
+        assert_eq!(levels.threshold(0, 96.0, 0.0), 96.0);
+        assert_eq!(levels.threshold(0, 96.0, -0.5), 255.0);
+    }
+
+    #[test]
+    fn shared_keeps_levels_every_channel_has() {
+        let shared = ChannelLevels::parse("rgb242").unwrap().shared().unwrap();
+        for c in 0..3 {
+            assert_eq!(shared.channel(c), &[0.0, 85.0, 170.0, 255.0]);
+        }
+        let shared = ChannelLevels::parse("rgb332").unwrap().shared().unwrap();
+        assert_eq!(shared.channel(1).len(), 2);
+        let disjoint = ChannelLevels::parse("0,255/128/0,255").unwrap();
+        assert!(disjoint.shared().is_err());
+    }
+}
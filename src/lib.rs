@@ -1,3 +1,18 @@
+pub mod color;
+pub mod dbs;
 pub mod dither;
+pub mod dot_diffusion;
 pub mod ffmpeg;
 pub mod frame;
+pub mod halftone;
+pub mod kd_tree;
+pub mod kernel;
+pub mod levels;
+pub mod motion;
+pub mod palette;
+pub mod palette_gen;
+pub mod pattern;
+pub mod riemersma;
+pub mod scene;
+pub mod temporal;
+pub mod threshold;
@@ -1,14 +1,24 @@
 use clap::Parser;
 use libc::{SIGINT, SIGTERM, c_int, signal};
-use rand;
 use rand::Rng;
 use std::fs;
 use std::path;
+use std::process;
+
+use dither_some::dither::{ColorMode, Quantizer, ScanOrder};
+use dither_some::dot_diffusion::ClassMatrix;
+use dither_some::halftone::{Halftone, Screen};
+use dither_some::kernel::{DiffusionKernel, VariableKernel};
+use dither_some::motion::MotionOpts;
+use dither_some::palette::Palette;
+use dither_some::palette_gen::{self, PaletteMethod, ScenePalettes};
+use dither_some::pattern::PlanCache;
+use dither_some::scene::SceneOpts;
+use dither_some::temporal::TemporalOpts;
+use dither_some::threshold::ThresholdMatrix;
+use dither_some::{dither, ffmpeg, frame};
 
 mod cli;
-mod dither;
-mod ffmpeg;
-mod frame;
 
 extern "C" fn handle_signal(_sig: c_int) {}
 
@@ -16,8 +26,8 @@ fn main() {
     // Ignore these signals from the OS, instead handle ffmpeg shutdown as
     // normal error so we have a chance to clean up.
     unsafe {
-        signal(SIGINT, handle_signal as usize);
-        signal(SIGTERM, handle_signal as usize);
+        signal(SIGINT, handle_signal as *const () as usize);
+        signal(SIGTERM, handle_signal as *const () as usize);
     }
 
     let args = cli::CliArgs::parse();
@@ -40,24 +50,400 @@ fn main() {
         Some(output_res) => output_res.resolve_fields(&input_res).unwrap(),
         None => input_res.clone(),
     };
+    let mut scene_palettes = None;
+    let palette = match (&args.palette, &args.palette_name) {
+        (Some(path), _) => match Palette::load_or_parse(path) {
+            Ok(palette) => Some(palette),
+            Err(e) => {
+                eprint!("{}", e);
+                return;
+            }
+        },
+        (None, Some(name)) => Palette::named(name),
+        (None, None) => match args.generate_palette {
+            Some(count) => {
+                let method = match args.palette_method {
+                    cli::CliPaletteMethod::MedianCut => {
+                        PaletteMethod::MedianCut
+                    }
+                    cli::CliPaletteMethod::Octree => PaletteMethod::Octree,
+                    cli::CliPaletteMethod::KMeans => PaletteMethod::KMeans {
+                        seed: args.palette_seed,
+                    },
+                };
+                let width = dither_res.width().unwrap();
+                let height = dither_res.height().unwrap();
+                let sample_every = args.palette_sample_every as usize;
+                let palette = match args.scene_threshold {
+                    // The first scene's palette stands in for the others
+                    // until the dithering swaps them in.
+                    Some(threshold) if args.scene_palettes => {
+                        palette_gen::sample_video_scenes(
+                            &args.input,
+                            width,
+                            height,
+                            sample_every,
+                            threshold,
+                        )
+                        .and_then(|scenes| {
+                            scenes
+                                .into_iter()
+                                .map(|(start, samples)| {
+                                    let palette = palette_gen::generate(
+                                        &samples,
+                                        count as usize,
+                                        method,
+                                    )?;
+                                    Ok((start, palette))
+                                })
+                                .collect::<Result<Vec<_>, String>>()
+                        })
+                        .map(|scenes| {
+                            let first = scenes[0].1.clone();
+                            let metric = args.color_metric.into();
+                            scene_palettes = Some(ScenePalettes {
+                                scenes: scenes
+                                    .into_iter()
+                                    .map(|(start, palette)| {
+                                        (start, palette.with_metric(metric))
+                                    })
+                                    .collect(),
+                                transition: args.palette_transition,
+                            });
+                            first
+                        })
+                    }
+                    _ => palette_gen::sample_video(
+                        &args.input,
+                        width,
+                        height,
+                        sample_every,
+                    )
+                    .and_then(|samples| {
+                        palette_gen::generate(&samples, count as usize, method)
+                    }),
+                };
+                match palette {
+                    Ok(palette) => Some(palette),
+                    Err(e) => {
+                        eprint!("{}", e);
+                        return;
+                    }
+                }
+            }
+            None => None,
+        },
+    }
+    .map(|palette| palette.with_metric(args.color_metric.into()));
+    if palette.is_none()
+        && args.algorithm.quantize_args().is_some_and(|quantize| {
+            quantize.color_mode() == Some(cli::CliColorMode::Palette)
+        })
+    {
+        eprint!(
+            "--color-mode palette requires --palette, --palette-name or --generate-palette"
+        );
+        return;
+    }
+    // A palette replaces the evenly spaced levels of every algorithm unless
+    // another color mode is asked for, and per channel levels replace
+    // --palette-count.
+    let quantizer = |quantize: &cli::QuantizeArgs, default_mode| {
+        let mode = match (quantize.color_mode(), &palette) {
+            (Some(cli::CliColorMode::Gray), _) => ColorMode::Gray,
+            (Some(cli::CliColorMode::Rgb), _) => ColorMode::Rgb,
+            (_, Some(palette)) => return Quantizer::Palette(palette.clone()),
+            (_, None) => default_mode,
+        };
+        match &quantize.levels {
+            Some(levels) => Quantizer::Levels {
+                levels: match mode {
+                    ColorMode::Gray => levels.shared().unwrap_or_else(|e| {
+                        eprint!("{}", e);
+                        process::exit(1)
+                    }),
+                    ColorMode::Rgb => levels.clone(),
+                },
+                mode,
+            },
+            None => Quantizer::Uniform {
+                palette_count: quantize.palette_count,
+                mode,
+            },
+        }
+    };
+
     let dither_algo_opts = match args.algorithm {
-        cli::CliAlgorithm::Atkinson { palette_count } => {
+        cli::CliAlgorithm::Atkinson { diffusion } => {
             dither::DitherAlgoOpts::Atkinson {
-                palette_count: palette_count,
+                quantizer: quantizer(&diffusion.quantize, ColorMode::Gray),
+                scan: diffusion.scan_order(),
             }
         }
-        cli::CliAlgorithm::FsColor { palette_count } => {
+        cli::CliAlgorithm::FsColor { diffusion } => {
             dither::DitherAlgoOpts::FsColor {
-                palette_count: palette_count,
+                quantizer: quantizer(&diffusion.quantize, ColorMode::Rgb),
+                scan: diffusion.scan_order(),
+            }
+        }
+        cli::CliAlgorithm::JarvisJudiceNinke { diffusion } => {
+            dither::DitherAlgoOpts::ErrorDiffusion {
+                kernel: DiffusionKernel::jarvis_judice_ninke(),
+                quantizer: quantizer(&diffusion.quantize, ColorMode::Rgb),
+                scan: diffusion.scan_order(),
+            }
+        }
+        cli::CliAlgorithm::Stucki { diffusion } => {
+            dither::DitherAlgoOpts::ErrorDiffusion {
+                kernel: DiffusionKernel::stucki(),
+                quantizer: quantizer(&diffusion.quantize, ColorMode::Rgb),
+                scan: diffusion.scan_order(),
+            }
+        }
+        cli::CliAlgorithm::Burkes { diffusion } => {
+            dither::DitherAlgoOpts::ErrorDiffusion {
+                kernel: DiffusionKernel::burkes(),
+                quantizer: quantizer(&diffusion.quantize, ColorMode::Rgb),
+                scan: diffusion.scan_order(),
+            }
+        }
+        cli::CliAlgorithm::Sierra { diffusion } => {
+            dither::DitherAlgoOpts::ErrorDiffusion {
+                kernel: DiffusionKernel::sierra(),
+                quantizer: quantizer(&diffusion.quantize, ColorMode::Rgb),
+                scan: diffusion.scan_order(),
+            }
+        }
+        cli::CliAlgorithm::TwoRowSierra { diffusion } => {
+            dither::DitherAlgoOpts::ErrorDiffusion {
+                kernel: DiffusionKernel::two_row_sierra(),
+                quantizer: quantizer(&diffusion.quantize, ColorMode::Rgb),
+                scan: diffusion.scan_order(),
+            }
+        }
+        cli::CliAlgorithm::SierraLite { diffusion } => {
+            dither::DitherAlgoOpts::ErrorDiffusion {
+                kernel: DiffusionKernel::sierra_lite(),
+                quantizer: quantizer(&diffusion.quantize, ColorMode::Rgb),
+                scan: diffusion.scan_order(),
+            }
+        }
+        cli::CliAlgorithm::Ostromoukhov { diffusion } => {
+            dither::DitherAlgoOpts::VariableDiffusion {
+                kernel: VariableKernel::Ostromoukhov,
+                quantizer: quantizer(&diffusion.quantize, ColorMode::Rgb),
+                scan: diffusion.scan_order(),
+            }
+        }
+        cli::CliAlgorithm::ZhouFang { diffusion } => {
+            dither::DitherAlgoOpts::VariableDiffusion {
+                kernel: VariableKernel::ZhouFang,
+                quantizer: quantizer(&diffusion.quantize, ColorMode::Rgb),
+                scan: diffusion.scan_order(),
+            }
+        }
+        cli::CliAlgorithm::CustomDiffusion {
+            kernel,
+            kernel_file,
+            diffusion,
+        } => {
+            let kernel = match (kernel, kernel_file) {
+                (Some(kernel), _) => kernel,
+                (None, Some(path)) => match DiffusionKernel::load(&path) {
+                    Ok(kernel) => kernel,
+                    Err(e) => {
+                        eprint!("{}", e);
+                        return;
+                    }
+                },
+                (None, None) => unreachable!("clap requires either"),
+            };
+            dither::DitherAlgoOpts::ErrorDiffusion {
+                kernel,
+                quantizer: quantizer(&diffusion.quantize, ColorMode::Rgb),
+                scan: diffusion.scan_order(),
+            }
+        }
+        cli::CliAlgorithm::DotDiffusion {
+            class_matrix,
+            quantize,
+        } => {
+            let classes = match class_matrix {
+                Some(path) => match ClassMatrix::load(&path) {
+                    Ok(classes) => classes,
+                    Err(e) => {
+                        eprint!("{}", e);
+                        return;
+                    }
+                },
+                None => ClassMatrix::knuth(),
+            };
+            dither::DitherAlgoOpts::DotDiffusion {
+                classes,
+                quantizer: quantizer(&quantize, ColorMode::Rgb),
+            }
+        }
+        cli::CliAlgorithm::Bayer { size, quantize } => {
+            dither::DitherAlgoOpts::Ordered {
+                matrix: ThresholdMatrix::bayer(size),
+                quantizer: quantizer(&quantize, ColorMode::Rgb),
+            }
+        }
+        cli::CliAlgorithm::BlueNoise {
+            size,
+            seed,
+            mask,
+            quantize,
+        } => {
+            let matrix = match mask {
+                Some(path) => match ThresholdMatrix::load(&path) {
+                    Ok(matrix) => matrix,
+                    Err(e) => {
+                        eprint!("{}", e);
+                        return;
+                    }
+                },
+                None => ThresholdMatrix::blue_noise(size as usize, seed),
+            };
+            dither::DitherAlgoOpts::Ordered {
+                matrix,
+                quantizer: quantizer(&quantize, ColorMode::Rgb),
+            }
+        }
+        cli::CliAlgorithm::Pattern { method, size, mask } => {
+            let Some(palette) = palette.clone() else {
+                eprint!(
+                    "Pattern dithering requires --palette or --palette-name"
+                );
+                return;
+            };
+            let matrix = match mask {
+                Some(path) => match ThresholdMatrix::load(&path) {
+                    Ok(matrix) => matrix,
+                    Err(e) => {
+                        eprint!("{}", e);
+                        return;
+                    }
+                },
+                None => ThresholdMatrix::bayer(size),
+            };
+            dither::DitherAlgoOpts::Pattern {
+                method: method.into(),
+                palette,
+                matrix,
+                plans: PlanCache::default(),
+            }
+        }
+        cli::CliAlgorithm::Halftone {
+            shape,
+            cell_size,
+            gray,
+            angle,
+            cyan_angle,
+            magenta_angle,
+            yellow_angle,
+        } => {
+            let screen = |angle| Screen::new(shape.into(), cell_size, angle);
+            let screens = if gray {
+                Halftone::Gray(screen(angle))
+            } else {
+                Halftone::Cmyk {
+                    cyan: screen(cyan_angle),
+                    magenta: screen(magenta_angle),
+                    yellow: screen(yellow_angle),
+                    black: screen(angle),
+                }
+            };
+            dither::DitherAlgoOpts::Halftone {
+                screens,
+                palette: palette.clone(),
+            }
+        }
+        cli::CliAlgorithm::Dbs {
+            initial,
+            max_iterations,
+            convergence,
+            quantize,
+        } => {
+            let quantizer = quantizer(&quantize, ColorMode::Rgb);
+            let initial = match initial {
+                cli::CliDbsInitial::Threshold => {
+                    dither::DitherAlgoOpts::Ordered {
+                        matrix: ThresholdMatrix::from_ranks(1, 1, &[0]),
+                        quantizer: quantizer.clone(),
+                    }
+                }
+                cli::CliDbsInitial::Atkinson => {
+                    dither::DitherAlgoOpts::Atkinson {
+                        quantizer: quantizer.clone(),
+                        scan: ScanOrder::Raster,
+                    }
+                }
+                cli::CliDbsInitial::FloydSteinberg => {
+                    dither::DitherAlgoOpts::FsColor {
+                        quantizer: quantizer.clone(),
+                        scan: ScanOrder::Serpentine,
+                    }
+                }
+                cli::CliDbsInitial::Bayer => dither::DitherAlgoOpts::Ordered {
+                    matrix: ThresholdMatrix::bayer(8),
+                    quantizer: quantizer.clone(),
+                },
+                cli::CliDbsInitial::BlueNoise => {
+                    dither::DitherAlgoOpts::Ordered {
+                        matrix: ThresholdMatrix::blue_noise(64, 0),
+                        quantizer: quantizer.clone(),
+                    }
+                }
+            };
+            dither::DitherAlgoOpts::Dbs {
+                initial: Box::new(initial),
+                max_iterations: max_iterations as usize,
+                convergence,
+                quantizer,
             }
         }
+        cli::CliAlgorithm::Random {
+            distribution,
+            seed,
+            reseed_per_frame,
+            quantize,
+        } => dither::DitherAlgoOpts::Random {
+            distribution: distribution.into(),
+            seed,
+            reseed_per_frame,
+            quantizer: quantizer(&quantize, ColorMode::Rgb),
+        },
+        cli::CliAlgorithm::Riemersma {
+            queue_length,
+            ratio,
+            quantize,
+        } => dither::DitherAlgoOpts::Riemersma {
+            queue_length: queue_length as usize,
+            ratio,
+            quantizer: quantizer(&quantize, ColorMode::Rgb),
+        },
     };
     let dither_opts = dither::DitherOpts {
-        dither_res: dither_res,
-        output_res: output_res,
+        dither_res,
+        output_res,
         input_path: args.input.clone(),
         output_path: temp_output_path.clone(),
         algo: dither_algo_opts,
+        linear: args.linear,
+        temporal: args.temporal_tolerance.map(|tolerance| TemporalOpts {
+            tolerance,
+            carry_error: args.carry_error.unwrap_or(0.0),
+            motion: args.motion_block_size.map(|block_size| MotionOpts {
+                block_size: block_size as usize,
+                search_range: args.motion_search_range as usize,
+            }),
+        }),
+        scenes: args.scene_threshold.map(|threshold| SceneOpts {
+            threshold,
+            report_path: args.scene_report.clone(),
+        }),
+        scene_palettes,
     };
 
     if let Err(e) = dither::dither_video(dither_opts) {
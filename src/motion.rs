@@ -0,0 +1,216 @@
+/// Options of block-based motion estimation between consecutive frames.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MotionOpts {
+    /// Width and height of the blocks that each get a motion vector.
+    pub block_size: usize,
+    /// Largest offset in pixels searched for a block's match.
+    pub search_range: usize,
+}
+
+/// One motion vector per block of an RGB24 frame, pointing from each block
+/// to where its content was in the previous frame.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MotionField {
+    width: usize,
+    height: usize,
+    block_size: usize,
+    blocks_w: usize,
+    vectors: Vec<(isize, isize)>,
+}
+
+impl MotionField {
+    /// Estimate the motion from previous to current, both RGB24 frames of
+    /// width x height, with a three step search minimizing each block's sum
+    /// of absolute differences.
+    pub fn estimate(
+        previous: &[u8],
+        current: &[u8],
+        width: usize,
+        height: usize,
+        opts: MotionOpts,
+    ) -> Self {
+        assert!(opts.block_size > 0);
+        assert!(previous.len() == width * height * 3);
+        assert!(current.len() == width * height * 3);
+
+        let blocks_w = width.div_ceil(opts.block_size);
+        let blocks_h = height.div_ceil(opts.block_size);
+        let range = opts.search_range as isize;
+
+        let mut vectors = Vec::with_capacity(blocks_w * blocks_h);
+        for by in 0..blocks_h {
+            for bx in 0..blocks_w {
+                let x0 = bx * opts.block_size;
+                let y0 = by * opts.block_size;
+                let x1 = (x0 + opts.block_size).min(width);
+                let y1 = (y0 + opts.block_size).min(height);
+                let sad = |(dx, dy): (isize, isize)| {
+                    block_sad(
+                        previous,
+                        current,
+                        width,
+                        (x0, y0, x1, y1),
+                        dx,
+                        dy,
+                    )
+                };
+                let fits = |(dx, dy): (isize, isize)| {
+                    x0 as isize + dx >= 0
+                        && y0 as isize + dy >= 0
+                        && x1 as isize + dx <= width as isize
+                        && y1 as isize + dy <= height as isize
+                };
+
+                let mut best = ((0, 0), sad((0, 0)));
+                let mut step = (range + 1) / 2;
+                while step > 0 && best.1 > 0 {
+                    let center = best.0;
+                    for (sx, sy) in NEIGHBORS {
+                        let candidate =
+                            (center.0 + sx * step, center.1 + sy * step);
+                        if candidate.0.abs() > range
+                            || candidate.1.abs() > range
+                            || !fits(candidate)
+                        {
+                            continue;
+                        }
+                        let cost = sad(candidate);
+                        if cost < best.1 {
+                            best = (candidate, cost);
+                        }
+                    }
+                    step /= 2;
+                }
+                vectors.push(best.0);
+            }
+        }
+
+        MotionField {
+            width,
+            height,
+            block_size: opts.block_size,
+            blocks_w,
+            vectors,
+        }
+    }
+
+    /// Motion vector of the block containing pixel (x, y).
+    pub fn vector(&self, x: usize, y: usize) -> (isize, isize) {
+        self.vectors[y / self.block_size * self.blocks_w + x / self.block_size]
+    }
+
+    /// Move previous, 3 values per pixel of the frames motion was estimated
+    /// between, along the motion so it lines up with the current frame.
+    /// Pixels moved in from outside the frame take the nearest edge's values.
+    pub fn warp<T: Copy>(&self, previous: &[T]) -> Vec<T> {
+        assert!(previous.len() == self.width * self.height * 3);
+
+        let mut warped = Vec::with_capacity(previous.len());
+        for y in 0..self.height {
+            for x in 0..self.width {
+                let (dx, dy) = self.vector(x, y);
+                let sx = (x as isize + dx).clamp(0, self.width as isize - 1);
+                let sy = (y as isize + dy).clamp(0, self.height as isize - 1);
+                let i = (sy as usize * self.width + sx as usize) * 3;
+                warped.extend_from_slice(&previous[i..i + 3]);
+            }
+        }
+        warped
+    }
+}
+
+const NEIGHBORS: [(isize, isize); 8] = [
+    (-1, -1),
+    (0, -1),
+    (1, -1),
+    (-1, 0),
+    (1, 0),
+    (-1, 1),
+    (0, 1),
+    (1, 1),
+];
+
+/// Sum of absolute differences between the block (x0, y0) to (x1, y1) of
+/// current and the same block of previous offset by (dx, dy).
+fn block_sad(
+    previous: &[u8],
+    current: &[u8],
+    width: usize,
+    (x0, y0, x1, y1): (usize, usize, usize, usize),
+    dx: isize,
+    dy: isize,
+) -> u32 {
+    let mut sad = 0;
+    for y in y0..y1 {
+        let row = y * width;
+        let shifted_row = (y as isize + dy) as usize * width;
+        let current = &current[(row + x0) * 3..(row + x1) * 3];
+        let start = (shifted_row as isize + x0 as isize + dx) as usize * 3;
+        let previous = &previous[start..start + current.len()];
+        sad += current
+            .iter()
+            .zip(previous)
+            .map(|(&a, &b)| a.abs_diff(b) as u32)
+            .sum::<u32>();
+    }
+    sad
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const OPTS: MotionOpts = MotionOpts {
+        block_size: 8,
+        search_range: 7,
+    };
+
+    /// A width x height RGB24 frame of a smooth pattern, shifted by (dx, dy).
+    fn pattern(width: usize, height: usize, dx: usize, dy: usize) -> Vec<u8> {
+        (0..height)
+            .flat_map(|y| (0..width).map(move |x| (x + 64 - dx, y + 64 - dy)))
+            .flat_map(|(x, y)| {
+                let v =
+                    ((x as f32 * 0.4).sin() * (y as f32 * 0.3).cos() * 100.0
+                        + 128.0) as u8;
+                [v, v / 2, 255 - v]
+            })
+            .collect()
+    }
+
+    #[test]
+    fn estimate_finds_zero_motion_of_static_frames() {
+        let frame = pattern(32, 32, 0, 0);
+        let field = MotionField::estimate(&frame, &frame, 32, 32, OPTS);
+        assert!(field.vectors.iter().all(|&v| v == (0, 0)));
+    }
+
+    #[test]
+    fn estimate_finds_shifts() {
+        let previous = pattern(48, 48, 0, 0);
+        let current = pattern(48, 48, 3, 2);
+        let field = MotionField::estimate(&previous, &current, 48, 48, OPTS);
+
+        // Blocks whose content came from outside the frame can't be matched.
+        for y in (8..40).step_by(8) {
+            for x in (8..40).step_by(8) {
+                assert_eq!(field.vector(x, y), (-3, -2));
+            }
+        }
+    }
+
+    #[test]
+    fn warp_lines_previous_up_with_current() {
+        let previous = pattern(48, 48, 0, 0);
+        let current = pattern(48, 48, 3, 2);
+        let field = MotionField::estimate(&previous, &current, 48, 48, OPTS);
+        let warped = field.warp(&previous);
+
+        for y in 8..40 {
+            for x in 8..40 {
+                let i = (y * 48 + x) * 3;
+                assert_eq!(warped[i..i + 3], current[i..i + 3]);
+            }
+        }
+    }
+}
@@ -0,0 +1,558 @@
+use std::fs;
+use std::path::Path;
+
+use crate::color::{ColorMetric, decode_srgb, encode_srgb, luma};
+use crate::frame::RgbPixel;
+use crate::kd_tree::KdTree;
+
+/// Palettes with more colors than this find the nearest color with a k-d tree
+/// rather than by checking every color, if the metric allows.
+const LINEAR_SEARCH_MAX_COLORS: usize = 16;
+
+/// An arbitrary, not necessarily uniform, set of colors to dither to.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Palette {
+    colors: Vec<RgbPixel>,
+    metric: ColorMetric,
+    /// Colors converted to the space of metric.
+    converted: Vec<(f32, f32, f32)>,
+    /// Whether colors are in linear light rather than sRGB.
+    linear: bool,
+    /// Tree of converted, if the metric is euclidean and the palette large.
+    tree: Option<KdTree>,
+}
+
+impl Palette {
+    pub fn new(colors: Vec<RgbPixel>) -> Result<Self, String> {
+        if colors.is_empty() {
+            return Err("Palette must have at least one color".into());
+        }
+        let palette = Palette {
+            colors,
+            metric: ColorMetric::Rgb,
+            converted: Vec::new(),
+            linear: false,
+            tree: None,
+        };
+        Ok(palette.reconvert())
+    }
+
+    /// The same palette, matching colors with metric.
+    pub fn with_metric(self, metric: ColorMetric) -> Self {
+        Palette { metric, ..self }.reconvert()
+    }
+
+    /// The same palette in linear light, to dither linear light frames with.
+    /// The RGB metric then measures distance in linear light, while the
+    /// perceptual metrics still see the sRGB colors.
+    pub fn linearized(&self) -> Self {
+        Palette {
+            colors: self.colors.iter().map(|&c| decode_srgb(c)).collect(),
+            linear: true,
+            ..self.clone()
+        }
+        .reconvert()
+    }
+
+    /// Parse hex colors such as "#000000,#ff8800,fff" separated by commas or
+    /// whitespace.
+    pub fn parse(s: &str) -> Result<Self, String> {
+        let colors = s
+            .split(|c: char| c == ',' || c.is_whitespace())
+            .filter(|hex| !hex.is_empty())
+            .map(parse_hex)
+            .collect::<Result<Vec<RgbPixel>, String>>()?;
+        Self::new(colors)
+    }
+
+    /// Load a GIMP (.gpl), JASC (.pal), Adobe color table (.act) or hex color
+    /// per line palette file. Text formats are told apart by their header.
+    pub fn load(path: &str) -> Result<Self, String> {
+        let bytes = fs::read(path)
+            .map_err(|e| format!("Reading palette '{}' failed: {}", path, e))?;
+        let is_act = Path::new(path)
+            .extension()
+            .is_some_and(|ext| ext.eq_ignore_ascii_case("act"));
+
+        let palette = if is_act {
+            parse_act(&bytes)
+        } else {
+            match String::from_utf8(bytes) {
+                Ok(text) if text.starts_with("GIMP Palette") => {
+                    parse_gpl(&text)
+                }
+                Ok(text) if text.starts_with("JASC-PAL") => parse_jasc(&text),
+                Ok(text) => parse_hex_lines(&text),
+                Err(_) => Err("Expected a text palette".into()),
+            }
+        };
+        palette.map_err(|e| format!("Invalid palette '{}': {}", path, e))
+    }
+
+    /// [Palette::load] a palette file, or [Palette::parse] s as hex colors if
+    /// it isn't a readable file.
+    pub fn load_or_parse(s: &str) -> Result<Self, String> {
+        Self::load(s).or_else(|e| Self::parse(s).map_err(|_| e))
+    }
+
+    /// One of the built-in palettes listed by [names].
+    pub fn named(name: &str) -> Option<Self> {
+        NAMED.iter().find(|(n, _)| *n == name).map(|(_, colors)| {
+            let colors = colors
+                .iter()
+                .map(|&c| {
+                    (
+                        (c >> 16) as f32,
+                        (c >> 8 & 0xff) as f32,
+                        (c & 0xff) as f32,
+                    )
+                })
+                .collect();
+            Palette::new(colors).unwrap()
+        })
+    }
+
+    pub fn colors(&self) -> &[RgbPixel] {
+        &self.colors
+    }
+
+    pub fn len(&self) -> usize {
+        self.colors.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.colors.is_empty()
+    }
+
+    pub fn get(&self, index: usize) -> RgbPixel {
+        self.colors[index]
+    }
+
+    pub fn metric(&self) -> ColorMetric {
+        self.metric
+    }
+
+    /// Index of the color closest to rgb by the palette's metric.
+    pub fn nearest(&self, rgb: RgbPixel) -> usize {
+        match &self.tree {
+            Some(tree) => tree.nearest(self.convert(rgb)),
+            None => self.nearest_linear(rgb),
+        }
+    }
+
+    /// Same as nearest, but always by checking every color.
+    pub fn nearest_linear(&self, rgb: RgbPixel) -> usize {
+        let target = self.convert(rgb);
+        self.converted
+            .iter()
+            .map(|&c| self.metric.compare(target, c))
+            .enumerate()
+            .min_by(|(_, a), (_, b)| a.total_cmp(b))
+            .map(|(i, _)| i)
+            .unwrap()
+    }
+
+    /// Distance between two colors by the palette's metric.
+    pub fn difference(&self, a: RgbPixel, b: RgbPixel) -> f32 {
+        self.metric.distance(self.convert(a), self.convert(b))
+    }
+
+    /// Mean distance from every color to its nearest other color, in the
+    /// channel that differs the most. For evenly spaced levels this is the
+    /// space between levels, which ordered and noise dithering offsets are
+    /// scaled to.
+    pub fn step(&self) -> f32 {
+        let nearest: Vec<f32> = self
+            .colors
+            .iter()
+            .filter_map(|&a| {
+                self.colors
+                    .iter()
+                    .map(|&b| max_channel_distance(a, b))
+                    .filter(|&d| d > 0.0)
+                    .min_by(f32::total_cmp)
+            })
+            .collect();
+        if nearest.is_empty() {
+            return 255.0;
+        }
+        nearest.iter().sum::<f32>() / nearest.len() as f32
+    }
+
+    fn convert(&self, rgb: RgbPixel) -> (f32, f32, f32) {
+        if self.linear && self.metric != ColorMetric::Rgb {
+            self.metric.convert(encode_srgb(rgb))
+        } else {
+            self.metric.convert(rgb)
+        }
+    }
+
+    fn reconvert(self) -> Self {
+        let converted: Vec<(f32, f32, f32)> =
+            self.colors.iter().map(|&c| self.convert(c)).collect();
+        let tree = (self.metric.is_euclidean()
+            && converted.len() > LINEAR_SEARCH_MAX_COLORS)
+            .then(|| KdTree::new(&converted));
+        Palette {
+            converted,
+            tree,
+            ..self
+        }
+    }
+
+    /// Luma of the color at index, which mixing plans are ordered by.
+    pub fn luma(&self, index: usize) -> f32 {
+        luma(self.colors[index])
+    }
+}
+
+/// Names of the built-in palettes.
+pub fn names() -> impl Iterator<Item = &'static str> {
+    NAMED.iter().map(|(name, _)| *name)
+}
+
+/// Squared euclidean distance between two colors.
+pub fn distance((r1, g1, b1): RgbPixel, (r2, g2, b2): RgbPixel) -> f32 {
+    (r1 - r2).powi(2) + (g1 - g2).powi(2) + (b1 - b2).powi(2)
+}
+
+/// GIMP palette: a "GIMP Palette" header, optional "Name:" and "Columns:"
+/// lines, then "R G B name" lines. Lines starting with '#' are comments.
+fn parse_gpl(text: &str) -> Result<Palette, String> {
+    let colors = text
+        .lines()
+        .skip(1)
+        .map(str::trim)
+        .filter(|line| {
+            !(line.is_empty()
+                || line.starts_with('#')
+                || line.starts_with("Name:")
+                || line.starts_with("Columns:"))
+        })
+        .map(parse_decimal_rgb)
+        .collect::<Result<Vec<RgbPixel>, String>>()?;
+    Palette::new(colors)
+}
+
+/// JASC palette: "JASC-PAL", a version line, the color count, then "R G B"
+/// lines.
+fn parse_jasc(text: &str) -> Result<Palette, String> {
+    let mut lines = text.lines().map(str::trim).filter(|l| !l.is_empty());
+    lines.next();
+    if lines.next() != Some("0100") {
+        return Err("Expected JASC-PAL version 0100".into());
+    }
+    let count = lines
+        .next()
+        .and_then(|line| line.parse::<usize>().ok())
+        .ok_or("Expected a color count")?;
+
+    let colors = lines
+        .map(parse_decimal_rgb)
+        .collect::<Result<Vec<RgbPixel>, String>>()?;
+    if colors.len() != count {
+        return Err(format!("Expected {} colors, got {}", count, colors.len()));
+    }
+    Palette::new(colors)
+}
+
+/// Adobe color table: 256 RGB triplets, optionally followed by a big endian
+/// count of the colors in use and the index of the transparent color.
+fn parse_act(bytes: &[u8]) -> Result<Palette, String> {
+    let count = match bytes.len() {
+        768 => 256,
+        772 => (u16::from_be_bytes([bytes[768], bytes[769]]) as usize).min(256),
+        len => return Err(format!("Expected 768 or 772 bytes, got {}", len)),
+    };
+
+    let colors = bytes[..count * 3]
+        .chunks(3)
+        .map(|c| (c[0] as f32, c[1] as f32, c[2] as f32))
+        .collect();
+    Palette::new(colors)
+}
+
+/// One hex color per line, where lines starting with ';' are comments. Colors
+/// may also be AARRGGBB like in paint.net palettes, whose alpha is ignored.
+fn parse_hex_lines(text: &str) -> Result<Palette, String> {
+    let colors = text
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with(';'))
+        .map(|line| {
+            let digits = line.strip_prefix('#').unwrap_or(line);
+            match digits.get(2..) {
+                Some(rgb) if digits.len() == 8 => parse_hex(rgb),
+                _ => parse_hex(line),
+            }
+            .map_err(|_| format!("Invalid hex color '{}'", line))
+        })
+        .collect::<Result<Vec<RgbPixel>, String>>()?;
+    Palette::new(colors)
+}
+
+/// Leading whitespace separated "R G B" channels of a line, ignoring anything
+/// after them such as a color name.
+fn parse_decimal_rgb(line: &str) -> Result<RgbPixel, String> {
+    let mut channels = line.split_whitespace().map(|c| c.parse::<u8>());
+    match (channels.next(), channels.next(), channels.next()) {
+        (Some(Ok(r)), Some(Ok(g)), Some(Ok(b))) => {
+            Ok((r as f32, g as f32, b as f32))
+        }
+        _ => Err(format!("Invalid color '{}'", line)),
+    }
+}
+
+fn max_channel_distance((r1, g1, b1): RgbPixel, (r2, g2, b2): RgbPixel) -> f32 {
+    (r1 - r2).abs().max((g1 - g2).abs()).max((b1 - b2).abs())
+}
+
+fn parse_hex(hex: &str) -> Result<RgbPixel, String> {
+    let digits = hex.strip_prefix('#').unwrap_or(hex);
+    let invalid = || format!("Invalid hex color '{}'", hex);
+    if !digits.is_ascii() {
+        return Err(invalid());
+    }
+
+    let channel = |s: &str| u8::from_str_radix(s, 16).map_err(|_| invalid());
+    let (r, g, b) = match digits.len() {
+        3 => {
+            let short = |i: usize| channel(&digits[i..i + 1]).map(|v| v * 17);
+            (short(0)?, short(1)?, short(2)?)
+        }
+        6 => (
+            channel(&digits[0..2])?,
+            channel(&digits[2..4])?,
+            channel(&digits[4..6])?,
+        ),
+        _ => return Err(invalid()),
+    };
+
+    Ok((r as f32, g as f32, b as f32))
+}
+
+/// Palettes of retro hardware, as 0xRRGGBB.
+#[rustfmt::skip]
+const NAMED: [(&str, &[u32]); 15] = [
+    // CGA 320x200 modes: palette 0, palette 1 and the unofficial mode 5
+    // palette, each in low and high intensity.
+    ("cga-0", &[0x000000, 0x00aa00, 0xaa0000, 0xaa5500]),
+    ("cga-0-high", &[0x000000, 0x55ff55, 0xff5555, 0xffff55]),
+    ("cga-1", &[0x000000, 0x00aaaa, 0xaa00aa, 0xaaaaaa]),
+    ("cga-1-high", &[0x000000, 0x55ffff, 0xff55ff, 0xffffff]),
+    ("cga-5", &[0x000000, 0x00aaaa, 0xaa0000, 0xaaaaaa]),
+    ("cga-5-high", &[0x000000, 0x55ffff, 0xff5555, 0xffffff]),
+    ("ega", &[
+        0x000000, 0x0000aa, 0x00aa00, 0x00aaaa,
+        0xaa0000, 0xaa00aa, 0xaa5500, 0xaaaaaa,
+        0x555555, 0x5555ff, 0x55ff55, 0x55ffff,
+        0xff5555, 0xff55ff, 0xffff55, 0xffffff,
+    ]),
+    // Pepto's measurements of the VIC-II.
+    ("c64", &[
+        0x000000, 0xffffff, 0x68372b, 0x70a4b2,
+        0x6f3d86, 0x588d43, 0x352879, 0xb8c76f,
+        0x6f4f25, 0x433900, 0x9a6759, 0x444444,
+        0x6c6c6c, 0x9ad284, 0x6c5eb5, 0x959595,
+    ]),
+    // The 2C02 PPU's colors, without its duplicate blacks.
+    ("nes", &[
+        0x7c7c7c, 0x0000fc, 0x0000bc, 0x4428bc, 0x940084, 0xa80020, 0xa81000,
+        0x881400, 0x503000, 0x007800, 0x006800, 0x005800, 0x004058, 0x000000,
+        0xbcbcbc, 0x0078f8, 0x0058f8, 0x6844fc, 0xd800cc, 0xe40058, 0xf83800,
+        0xe45c10, 0xac7c00, 0x00b800, 0x00a800, 0x00a844, 0x008888,
+        0xf8f8f8, 0x3cbcfc, 0x6888fc, 0x9878f8, 0xf878f8, 0xf85898, 0xf87858,
+        0xfca044, 0xf8b800, 0xb8f818, 0x58d854, 0x58f898, 0x00e8d8, 0x787878,
+        0xfcfcfc, 0xa4e4fc, 0xb8b8f8, 0xd8b8f8, 0xf8b8f8, 0xf8a4c0, 0xf0d0b0,
+        0xfce0a8, 0xf8d878, 0xd8f878, 0xb8f8b8, 0xb8f8d8, 0x00fcfc, 0xf8d8f8,
+    ]),
+    ("gameboy", &[0x0f380f, 0x306230, 0x8bac0f, 0x9bbc0f]),
+    ("pico-8", &[
+        0x000000, 0x1d2b53, 0x7e2553, 0x008751,
+        0xab5236, 0x5f574f, 0xc2c3c7, 0xfff1e8,
+        0xff004d, 0xffa300, 0xffec27, 0x00e436,
+        0x29adff, 0x83769c, 0xff77a8, 0xffccaa,
+    ]),
+    // Normal and bright colors, bright black being the same as black.
+    ("zx-spectrum", &[
+        0x000000, 0x0000d7, 0xd70000, 0xd700d7,
+        0x00d700, 0x00d7d7, 0xd7d700, 0xd7d7d7,
+        0x0000ff, 0xff0000, 0xff00ff, 0x00ff00,
+        0x00ffff, 0xffff00, 0xffffff,
+    ]),
+    ("mac-1bit", &[0x000000, 0xffffff]),
+    // Lo-res colors, with its two identical grays merged.
+    ("apple-ii", &[
+        0x000000, 0x722640, 0x40337f, 0xe434fe,
+        0x0e5940, 0x808080, 0x1b9afe, 0xbfb3ff,
+        0x404c00, 0xe46501, 0xf1a6bf, 0x1bcb01,
+        0xbfcc80, 0x8dd9bf, 0xffffff,
+    ]),
+    ("teletext", &[
+        0x000000, 0xff0000, 0x00ff00, 0xffff00,
+        0x0000ff, 0xff00ff, 0x00ffff, 0xffffff,
+    ]),
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_reads_hex_colors() {
+        let palette = Palette::parse("#000000, ff8800\n#fff").unwrap();
+        assert_eq!(
+            palette.colors(),
+            &[(0.0, 0.0, 0.0), (255.0, 136.0, 0.0), (255.0, 255.0, 255.0)]
+        );
+    }
+
+    #[test]
+    fn parse_rejects_invalid_colors() {
+        for s in ["", "#12345", "#gggggg", "#0000000", "#ééé"] {
+            assert!(Palette::parse(s).is_err(), "{:?}", s);
+        }
+    }
+
+    #[test]
+    fn nearest_picks_closest_color() {
+        let palette = Palette::parse("#000000 #ff0000 #ffffff").unwrap();
+        assert_eq!(palette.nearest((200.0, 30.0, 40.0)), 1);
+        assert_eq!(palette.nearest((200.0, 190.0, 210.0)), 2);
+        assert_eq!(palette.nearest((20.0, 10.0, 0.0)), 0);
+    }
+
+    #[test]
+    fn nearest_uses_metric() {
+        // Dark blue is closer to black in RGB but closer to the bright blue
+        // in perceptual spaces, where a small blue difference is more visible.
+        let palette = Palette::parse("#000000 #0000ff").unwrap();
+        let rgb = (0.0, 0.0, 110.0);
+        assert_eq!(palette.nearest(rgb), 0);
+        for metric in
+            [ColorMetric::Lab, ColorMetric::Ciede2000, ColorMetric::Oklab]
+        {
+            let palette = palette.clone().with_metric(metric);
+            assert_eq!(palette.metric(), metric);
+            assert_eq!(palette.nearest(rgb), 1, "{:?}", metric);
+        }
+    }
+
+    #[test]
+    fn nearest_of_large_palettes_matches_linear_search() {
+        let colors = (0..64)
+            .map(|i| ((i * 37 % 256) as f32, (i * 91 % 256) as f32, i as f32))
+            .collect();
+        let palette = Palette::new(colors).unwrap();
+        for metric in [ColorMetric::Rgb, ColorMetric::Oklab] {
+            let palette = palette.clone().with_metric(metric);
+            for rgb in
+                [(0.0, 0.0, 0.0), (200.0, 30.0, 40.0), (90.0, 250.0, 7.0)]
+            {
+                assert_eq!(palette.nearest(rgb), palette.nearest_linear(rgb));
+            }
+        }
+    }
+
+    #[test]
+    fn step_matches_evenly_spaced_levels() {
+        let gray = Palette::parse("#000 #555 #aaa #fff").unwrap();
+        assert_eq!(gray.step(), 85.0);
+
+        let mut cube = Vec::new();
+        for r in [0.0, 255.0] {
+            for g in [0.0, 255.0] {
+                for b in [0.0, 255.0] {
+                    cube.push((r, g, b));
+                }
+            }
+        }
+        assert_eq!(Palette::new(cube).unwrap().step(), 255.0);
+        assert_eq!(Palette::parse("#123456").unwrap().step(), 255.0);
+    }
+
+    #[test]
+    fn parse_gpl_reads_colors() {
+        let gpl = "GIMP Palette\nName: Test\nColumns: 2\n# comment\n\
+                   0 0 0\tBlack\n255 128   0 Orange\n";
+        assert_eq!(
+            parse_gpl(gpl).unwrap().colors(),
+            &[(0.0, 0.0, 0.0), (255.0, 128.0, 0.0)]
+        );
+        assert!(parse_gpl("GIMP Palette\n0 0\n").is_err());
+    }
+
+    #[test]
+    fn parse_jasc_checks_count() {
+        let jasc = "JASC-PAL\r\n0100\r\n2\r\n0 0 0\r\n255 255 255\r\n";
+        assert_eq!(parse_jasc(jasc).unwrap().len(), 2);
+        assert!(parse_jasc("JASC-PAL\n0100\n3\n0 0 0\n").is_err());
+    }
+
+    #[test]
+    fn parse_act_uses_trailing_count() {
+        let mut act = vec![0u8; 768];
+        act[3..6].copy_from_slice(&[10, 20, 30]);
+        assert_eq!(parse_act(&act).unwrap().len(), 256);
+
+        act.extend_from_slice(&[0, 2, 0xff, 0xff]);
+        assert_eq!(
+            parse_act(&act).unwrap().colors(),
+            &[(0.0, 0.0, 0.0), (10.0, 20.0, 30.0)]
+        );
+        assert!(parse_act(&act[..700]).is_err());
+    }
+
+    #[test]
+    fn parse_hex_lines_skips_comments() {
+        let hex = ";palette\n#ff0000\n\n00ff00\n";
+        assert_eq!(parse_hex_lines(hex).unwrap().len(), 2);
+    }
+
+    #[test]
+    fn parse_hex_lines_reads_paint_net_palettes() {
+        let hex = ";paint.net Palette File\n;Colors: 2\nFFFF8800\n800000FF\n";
+        assert_eq!(
+            parse_hex_lines(hex).unwrap().colors(),
+            [(255.0, 136.0, 0.0), (0.0, 0.0, 255.0)]
+        );
+        assert!(parse_hex_lines("FFFF880\n").is_err());
+    }
+
+    #[test]
+    fn load_or_parse_accepts_inline_colors() {
+        let palette = Palette::load_or_parse("#000000,#ff8800,fff").unwrap();
+        assert_eq!(palette.len(), 3);
+        assert!(Palette::load_or_parse("no/such/palette.gpl").is_err());
+    }
+
+    #[test]
+    fn named_palettes_have_expected_sizes() {
+        for (name, len) in [
+            ("cga-1-high", 4),
+            ("ega", 16),
+            ("c64", 16),
+            ("nes", 55),
+            ("gameboy", 4),
+            ("pico-8", 16),
+            ("zx-spectrum", 15),
+            ("mac-1bit", 2),
+            ("apple-ii", 15),
+            ("teletext", 8),
+        ] {
+            assert_eq!(Palette::named(name).unwrap().len(), len, "{}", name);
+        }
+        assert!(names().all(|name| Palette::named(name).is_some()));
+        assert!(Palette::named("vga").is_none());
+    }
+
+    #[test]
+    fn named_palette_colors_are_unique() {
+        for name in names() {
+            let colors = Palette::named(name).unwrap().colors;
+            for (i, a) in colors.iter().enumerate() {
+                assert!(!colors[i + 1..].contains(a), "{} {:?}", name, a);
+            }
+        }
+    }
+}
@@ -0,0 +1,599 @@
+use std::collections::HashMap;
+use std::io::Read;
+
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+use crate::ffmpeg;
+use crate::frame::RgbPixel;
+use crate::palette::{Palette, distance};
+use crate::scene::SceneDetector;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PaletteMethod {
+    /// Recursively split the box of colors with the widest channel range at
+    /// its median.
+    MedianCut,
+    /// Merge the least common leaves of an octree of colors.
+    Octree,
+    /// Lloyd's k-means, starting from seeded k-means++ centers.
+    KMeans { seed: u64 },
+}
+
+/// Most pixels sampled from a single frame of a video.
+const SAMPLES_PER_FRAME: usize = 16384;
+
+/// Most pixels sampled from a whole video, or from a scene of it.
+const MAX_SAMPLES: usize = 1 << 20;
+
+/// A palette for each scene of a video.
+#[derive(Debug, Clone)]
+pub struct ScenePalettes {
+    /// Index of the first frame of every scene and its palette, in order.
+    pub scenes: Vec<(u64, Palette)>,
+    /// Frames over which each scene's palette fades in from the previous
+    /// scene's.
+    pub transition: u32,
+}
+
+impl ScenePalettes {
+    /// Palette to dither frame_index with, or None if it is the same as the
+    /// previous frame's.
+    pub fn palette_at(&self, frame_index: u64) -> Option<Palette> {
+        let scene = self
+            .scenes
+            .partition_point(|&(start, _)| start <= frame_index)
+            .checked_sub(1)?;
+        let (start, palette) = &self.scenes[scene];
+        let offset = frame_index - start;
+
+        if scene > 0 && offset < self.transition as u64 {
+            let t = (offset + 1) as f32 / (self.transition + 1) as f32;
+            Some(blend(&self.scenes[scene - 1].1, palette, t))
+        } else if offset == 0 || (scene > 0 && offset == self.transition as u64)
+        {
+            Some(palette.clone())
+        } else {
+            None
+        }
+    }
+}
+
+/// Colors of to, each moved the fraction 1 - t of the way to its nearest color
+/// in from.
+fn blend(from: &Palette, to: &Palette, t: f32) -> Palette {
+    let colors = to
+        .colors()
+        .iter()
+        .map(|&(r, g, b)| {
+            let (fr, fg, fb) = from.get(from.nearest((r, g, b)));
+            (fr + (r - fr) * t, fg + (g - fg) * t, fb + (b - fb) * t)
+        })
+        .collect();
+    Palette::new(colors).unwrap().with_metric(to.metric())
+}
+
+/// Derive a palette of at most count colors from pixels. Fewer colors are
+/// returned if pixels has fewer distinct colors.
+pub fn generate(
+    pixels: &[RgbPixel],
+    count: usize,
+    method: PaletteMethod,
+) -> Result<Palette, String> {
+    if pixels.is_empty() {
+        return Err("No pixels to generate a palette from".into());
+    }
+    assert!(count > 0);
+
+    let colors = match method {
+        PaletteMethod::MedianCut => median_cut(pixels, count),
+        PaletteMethod::Octree => octree(pixels, count),
+        PaletteMethod::KMeans { seed } => k_means(pixels, count, seed),
+    };
+    let colors = colors
+        .into_iter()
+        .map(|(r, g, b)| (r.round(), g.round(), b.round()))
+        .collect();
+    Palette::new(colors)
+}
+
+/// Pixels of every frame_step-th frame of a video scaled to width x height,
+/// evenly subsampled to at most SAMPLES_PER_FRAME per frame, and randomly to
+/// at most MAX_SAMPLES in total.
+pub fn sample_video(
+    path: &str,
+    width: isize,
+    height: isize,
+    frame_step: usize,
+) -> Result<Vec<RgbPixel>, String> {
+    assert!(frame_step > 0);
+
+    let pixel_count = width as usize * height as usize;
+    let pixel_step = pixel_count.div_ceil(SAMPLES_PER_FRAME).max(1);
+    let mut frame_buf = vec![0u8; pixel_count * 3];
+    let mut frame_reader = ffmpeg::spawn_frame_reader(path, width, height)?;
+
+    let mut samples = Reservoir::new(MAX_SAMPLES);
+    for frame_index in 0.. {
+        if frame_reader.read_exact(&mut frame_buf).is_err() {
+            break;
+        }
+        if frame_index % frame_step != 0 {
+            continue;
+        }
+        sample_frame(&frame_buf, pixel_step, &mut samples);
+    }
+
+    Ok(samples.samples)
+}
+
+/// Pixels of a video like sample_video, split into scenes where consecutive
+/// frames differ by more than threshold. Returns the index of the first frame
+/// of every scene and its pixels, sampled from every frame_step-th frame
+/// counting from the scene's first.
+pub fn sample_video_scenes(
+    path: &str,
+    width: isize,
+    height: isize,
+    frame_step: usize,
+    threshold: f32,
+) -> Result<Vec<(u64, Vec<RgbPixel>)>, String> {
+    assert!(frame_step > 0);
+
+    let pixel_count = width as usize * height as usize;
+    let pixel_step = pixel_count.div_ceil(SAMPLES_PER_FRAME).max(1);
+    let mut frame_buf = vec![0u8; pixel_count * 3];
+    let mut frame_reader = ffmpeg::spawn_frame_reader(path, width, height)?;
+    let mut detector = SceneDetector::new(threshold);
+
+    let mut scenes = vec![(0, Reservoir::new(MAX_SAMPLES))];
+    for frame_index in 0.. {
+        if frame_reader.read_exact(&mut frame_buf).is_err() {
+            break;
+        }
+        if detector.detect(frame_index, &frame_buf).is_some() {
+            scenes.push((frame_index, Reservoir::new(MAX_SAMPLES)));
+        }
+        let (start, samples) = scenes.last_mut().unwrap();
+        if (frame_index - *start) % frame_step as u64 != 0 {
+            continue;
+        }
+        sample_frame(&frame_buf, pixel_step, samples);
+    }
+
+    Ok(scenes
+        .into_iter()
+        .map(|(start, samples)| (start, samples.samples))
+        .collect())
+}
+
+/// Offer every pixel_step-th pixel of an RGB24 frame to samples.
+fn sample_frame(frame: &[u8], pixel_step: usize, samples: &mut Reservoir) {
+    for p in frame.chunks(3).step_by(pixel_step) {
+        samples.offer((p[0] as f32, p[1] as f32, p[2] as f32));
+    }
+}
+
+/// A uniformly random sample of at most capacity of the pixels offered to it,
+/// the same for the same pixels.
+struct Reservoir {
+    samples: Vec<RgbPixel>,
+    capacity: usize,
+    offered: usize,
+    rng: StdRng,
+}
+
+impl Reservoir {
+    fn new(capacity: usize) -> Self {
+        Reservoir {
+            samples: Vec::new(),
+            capacity,
+            offered: 0,
+            rng: StdRng::seed_from_u64(0),
+        }
+    }
+
+    fn offer(&mut self, pixel: RgbPixel) {
+        if self.samples.len() < self.capacity {
+            self.samples.push(pixel);
+        } else {
+            let i = self.rng.random_range(0..=self.offered);
+            if i < self.capacity {
+                self.samples[i] = pixel;
+            }
+        }
+        self.offered += 1;
+    }
+}
+
+fn mean(pixels: &[RgbPixel]) -> RgbPixel {
+    let n = pixels.len() as f32;
+    let (r, g, b) = pixels
+        .iter()
+        .fold((0.0, 0.0, 0.0), |(r, g, b), p| (r + p.0, g + p.1, b + p.2));
+    (r / n, g / n, b / n)
+}
+
+fn channel((r, g, b): RgbPixel, c: usize) -> f32 {
+    [r, g, b][c]
+}
+
+fn median_cut(pixels: &[RgbPixel], count: usize) -> Vec<RgbPixel> {
+    // Widest channel range of a box, and which channel it is.
+    let widest = |pixels: &[RgbPixel]| -> (f32, usize) {
+        (0..3)
+            .map(|c| {
+                let (min, max) = pixels.iter().fold(
+                    (f32::INFINITY, f32::NEG_INFINITY),
+                    |(min, max), &p| {
+                        (min.min(channel(p, c)), max.max(channel(p, c)))
+                    },
+                );
+                (max - min, c)
+            })
+            .max_by(|a, b| a.0.total_cmp(&b.0))
+            .unwrap()
+    };
+
+    let mut boxes = vec![pixels.to_vec()];
+    while boxes.len() < count {
+        let Some((i, (range, c))) = boxes
+            .iter()
+            .map(|b| widest(b))
+            .enumerate()
+            .max_by(|a, b| a.1.0.total_cmp(&b.1.0))
+        else {
+            break;
+        };
+        if range == 0.0 {
+            break;
+        }
+
+        let mut split = boxes.swap_remove(i);
+        split.sort_by(|a, b| channel(*a, c).total_cmp(&channel(*b, c)));
+        // Split at the median, but never between two equal values so both
+        // halves keep a range of their own.
+        let median = channel(split[split.len() / 2], c);
+        let at = split.partition_point(|&p| channel(p, c) < median).max(1);
+        let upper = split.split_off(at);
+        boxes.push(split);
+        boxes.push(upper);
+    }
+
+    boxes.iter().map(|b| mean(b)).collect()
+}
+
+/// Node of an octree of colors, children indexed by one bit of each channel.
+#[derive(Clone, Default)]
+struct OctreeNode {
+    children: [Option<usize>; 8],
+    is_leaf: bool,
+    /// Pixels in the leaves below the node, or in the node if it's a leaf.
+    count: usize,
+    /// Sum of the pixels of a leaf.
+    sum: (f64, f64, f64),
+}
+
+fn octree(pixels: &[RgbPixel], count: usize) -> Vec<RgbPixel> {
+    const DEPTH: usize = 8;
+
+    let mut nodes = vec![OctreeNode::default()];
+    let mut free = Vec::new();
+    // Internal nodes at every depth, the deepest of which are merged first.
+    let mut levels: Vec<Vec<usize>> = vec![vec![0]; 1];
+    levels.resize(DEPTH, Vec::new());
+    let mut leaves = 0;
+
+    for &(r, g, b) in pixels {
+        let (r, g, b) = (r as u8, g as u8, b as u8);
+        let mut node = 0;
+        for depth in 0..DEPTH {
+            if nodes[node].is_leaf {
+                break;
+            }
+            nodes[node].count += 1;
+            let bit = 7 - depth;
+            let child = ((r >> bit & 1) << 2
+                | (g >> bit & 1) << 1
+                | (b >> bit & 1)) as usize;
+            node = match nodes[node].children[child] {
+                Some(existing) => existing,
+                None => {
+                    let created = OctreeNode {
+                        is_leaf: depth + 1 == DEPTH,
+                        ..Default::default()
+                    };
+                    let index = match free.pop() {
+                        Some(index) => {
+                            nodes[index] = created;
+                            index
+                        }
+                        None => {
+                            nodes.push(created);
+                            nodes.len() - 1
+                        }
+                    };
+                    nodes[node].children[child] = Some(index);
+                    if depth + 1 == DEPTH {
+                        leaves += 1;
+                    } else {
+                        levels[depth + 1].push(index);
+                    }
+                    index
+                }
+            };
+        }
+        let leaf = &mut nodes[node];
+        leaf.count += 1;
+        leaf.sum.0 += r as f64;
+        leaf.sum.1 += g as f64;
+        leaf.sum.2 += b as f64;
+
+        // Merge the least common of the deepest internal nodes into leaves
+        // until there are few enough.
+        while leaves > count {
+            let level = levels.iter_mut().rev().find(|l| !l.is_empty());
+            let Some(level) = level else { break };
+            let (position, &merged) = level
+                .iter()
+                .enumerate()
+                .min_by_key(|&(_, &n)| nodes[n].count)
+                .unwrap();
+            level.swap_remove(position);
+
+            let children = std::mem::take(&mut nodes[merged].children);
+            for child in children.into_iter().flatten() {
+                let removed = std::mem::take(&mut nodes[child]);
+                let node = &mut nodes[merged];
+                node.sum.0 += removed.sum.0;
+                node.sum.1 += removed.sum.1;
+                node.sum.2 += removed.sum.2;
+                free.push(child);
+                leaves -= 1;
+            }
+            nodes[merged].is_leaf = true;
+            leaves += 1;
+        }
+    }
+
+    let mut colors = Vec::with_capacity(leaves);
+    let mut stack = vec![0];
+    while let Some(node) = stack.pop() {
+        let node = &nodes[node];
+        if node.is_leaf {
+            if node.count > 0 {
+                let n = node.count as f64;
+                colors.push((
+                    (node.sum.0 / n) as f32,
+                    (node.sum.1 / n) as f32,
+                    (node.sum.2 / n) as f32,
+                ));
+            }
+        } else {
+            stack.extend(node.children.iter().flatten());
+        }
+    }
+    colors
+}
+
+fn k_means(pixels: &[RgbPixel], count: usize, seed: u64) -> Vec<RgbPixel> {
+    const MAX_ITERATIONS: usize = 32;
+
+    // Cluster distinct colors weighted by how often they occur.
+    let mut weights: HashMap<(u8, u8, u8), usize> = HashMap::new();
+    for &(r, g, b) in pixels {
+        *weights.entry((r as u8, g as u8, b as u8)).or_default() += 1;
+    }
+    let mut points: Vec<(RgbPixel, f32)> = weights
+        .into_iter()
+        .map(|((r, g, b), w)| ((r as f32, g as f32, b as f32), w as f32))
+        .collect();
+    // HashMap order is random, sort so the same seed gives the same palette.
+    points.sort_by(|a, b| {
+        (a.0.0, a.0.1, a.0.2)
+            .partial_cmp(&(b.0.0, b.0.1, b.0.2))
+            .unwrap()
+    });
+    if points.len() <= count {
+        return points.into_iter().map(|(p, _)| p).collect();
+    }
+
+    // k-means++: every next center is picked with a probability proportional
+    // to its squared distance to the nearest center so far.
+    let mut rng = StdRng::seed_from_u64(seed);
+    let weights: Vec<f32> = points.iter().map(|&(_, w)| w).collect();
+    let mut centers = vec![points[pick_weighted(&mut rng, &weights)].0];
+    let mut nearest: Vec<f32> = points
+        .iter()
+        .map(|&(p, _)| distance(p, centers[0]))
+        .collect();
+    while centers.len() < count {
+        let chances: Vec<f32> =
+            weights.iter().zip(&nearest).map(|(w, d)| w * d).collect();
+        let center = points[pick_weighted(&mut rng, &chances)].0;
+        for (d, &(p, _)) in nearest.iter_mut().zip(&points) {
+            *d = d.min(distance(p, center));
+        }
+        centers.push(center);
+    }
+
+    let mut assignment = vec![usize::MAX; points.len()];
+    for _ in 0..MAX_ITERATIONS {
+        let mut changed = false;
+        for (a, &(p, _)) in assignment.iter_mut().zip(&points) {
+            let closest = (0..centers.len())
+                .min_by(|&i, &j| {
+                    distance(p, centers[i]).total_cmp(&distance(p, centers[j]))
+                })
+                .unwrap();
+            if *a != closest {
+                *a = closest;
+                changed = true;
+            }
+        }
+        if !changed {
+            break;
+        }
+
+        let mut sums = vec![(0.0, 0.0, 0.0, 0.0); centers.len()];
+        for (&a, &((r, g, b), w)) in assignment.iter().zip(&points) {
+            let sum = &mut sums[a];
+            *sum = (sum.0 + r * w, sum.1 + g * w, sum.2 + b * w, sum.3 + w);
+        }
+        for (center, (r, g, b, w)) in centers.iter_mut().zip(sums) {
+            // An empty cluster keeps its center.
+            if w > 0.0 {
+                *center = (r / w, g / w, b / w);
+            }
+        }
+    }
+
+    centers
+}
+
+/// Index of a weight, picked with a probability proportional to it.
+fn pick_weighted(rng: &mut StdRng, weights: &[f32]) -> usize {
+    let total: f32 = weights.iter().sum();
+    let mut target = rng.random::<f32>() * total;
+    for (i, &w) in weights.iter().enumerate() {
+        if target < w {
+            return i;
+        }
+        target -= w;
+    }
+    weights.len() - 1
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const METHODS: [PaletteMethod; 3] = [
+        PaletteMethod::MedianCut,
+        PaletteMethod::Octree,
+        PaletteMethod::KMeans { seed: 0 },
+    ];
+
+    /// Noisy pixels around a few well separated colors.
+    fn clustered_pixels(centers: &[RgbPixel]) -> Vec<RgbPixel> {
+        let mut rng = StdRng::seed_from_u64(1);
+        let mut pixels = Vec::new();
+        for &(r, g, b) in centers {
+            for _ in 0..500 {
+                let mut jitter = || rng.random_range(-6.0..6.0);
+                pixels.push((r + jitter(), g + jitter(), b + jitter()));
+            }
+        }
+        pixels
+    }
+
+    #[test]
+    fn generate_finds_clusters() {
+        let centers = [
+            (20.0, 20.0, 20.0),
+            (230.0, 40.0, 40.0),
+            (40.0, 200.0, 60.0),
+            (60.0, 60.0, 220.0),
+        ];
+        let pixels = clustered_pixels(&centers);
+
+        for method in METHODS {
+            let palette = generate(&pixels, 4, method).unwrap();
+            assert_eq!(palette.len(), 4, "{:?}", method);
+            for &center in &centers {
+                let nearest = palette.get(palette.nearest(center));
+                assert!(
+                    distance(center, nearest).sqrt() < 12.0,
+                    "{:?} has no color near {:?}: {:?}",
+                    method,
+                    center,
+                    palette.colors()
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn generate_returns_fewer_colors_for_fewer_distinct_pixels() {
+        let pixels = [(0.0, 0.0, 0.0), (255.0, 255.0, 255.0)].repeat(50);
+        for method in METHODS {
+            let palette = generate(&pixels, 16, method).unwrap();
+            assert_eq!(palette.len(), 2, "{:?}", method);
+        }
+        assert!(generate(&[], 4, PaletteMethod::MedianCut).is_err());
+    }
+
+    #[test]
+    fn k_means_is_deterministic_per_seed() {
+        let pixels = clustered_pixels(&[
+            (10.0, 100.0, 200.0),
+            (200.0, 100.0, 10.0),
+            (120.0, 120.0, 120.0),
+        ]);
+        let method = PaletteMethod::KMeans { seed: 3 };
+        assert_eq!(
+            generate(&pixels, 8, method).unwrap(),
+            generate(&pixels, 8, method).unwrap()
+        );
+    }
+
+    #[test]
+    fn scene_palettes_switch_at_cuts() {
+        let black = Palette::new(vec![(0.0, 0.0, 0.0)]).unwrap();
+        let white = Palette::new(vec![(255.0, 255.0, 255.0)]).unwrap();
+        let palettes = ScenePalettes {
+            scenes: vec![(0, black.clone()), (10, white.clone())],
+            transition: 0,
+        };
+
+        assert_eq!(palettes.palette_at(0), Some(black));
+        assert_eq!(palettes.palette_at(9), None);
+        assert_eq!(palettes.palette_at(10), Some(white));
+        assert_eq!(palettes.palette_at(11), None);
+    }
+
+    #[test]
+    fn scene_palettes_fade_in_over_transition() {
+        let black = Palette::new(vec![(0.0, 0.0, 0.0)]).unwrap();
+        let colors = vec![(255.0, 255.0, 255.0), (0.0, 0.0, 255.0)];
+        let palettes = ScenePalettes {
+            scenes: vec![
+                (0, black.clone()),
+                (10, Palette::new(colors.clone()).unwrap()),
+            ],
+            transition: 3,
+        };
+
+        let gray = palettes.palette_at(11).unwrap();
+        assert_eq!(gray.colors(), [(127.5, 127.5, 127.5), (0.0, 0.0, 127.5)]);
+        assert_eq!(palettes.palette_at(13).unwrap().colors(), colors);
+        assert_eq!(palettes.palette_at(14), None);
+    }
+
+    #[test]
+    fn first_scene_palette_is_set_once_with_a_transition() {
+        let black = Palette::new(vec![(0.0, 0.0, 0.0)]).unwrap();
+        let white = Palette::new(vec![(255.0, 255.0, 255.0)]).unwrap();
+        let palettes = ScenePalettes {
+            scenes: vec![(0, black.clone()), (10, white)],
+            transition: 3,
+        };
+
+        assert_eq!(palettes.palette_at(0), Some(black));
+        for frame_index in 1..10 {
+            assert_eq!(palettes.palette_at(frame_index), None);
+        }
+    }
+
+    #[test]
+    fn reservoir_keeps_at_most_capacity_samples_of_every_part() {
+        let mut reservoir = Reservoir::new(1000);
+        for i in 0..100_000 {
+            reservoir.offer((i as f32, 0.0, 0.0));
+        }
+        assert_eq!(reservoir.samples.len(), 1000);
+        // Every tenth of the offered pixels is about as well represented.
+        let late = reservoir.samples.iter().filter(|p| p.0 >= 90_000.0);
+        assert!((50..150).contains(&late.count()));
+    }
+}
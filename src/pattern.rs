@@ -0,0 +1,270 @@
+use std::collections::HashMap;
+use std::sync::{Mutex, MutexGuard};
+
+use crate::frame::RgbPixel;
+use crate::palette::{Palette, distance};
+
+/// How a mixing plan is built for a color. A mixing plan is a list of palette
+/// indices ordered by luma, which averages out to about the color. A threshold
+/// matrix then picks one entry of the plan for every pixel.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PatternMethod {
+    /// Yliluoma's algorithm 1, mixing the best pair of palette colors.
+    Yliluoma1,
+    /// Yliluoma's algorithm 2, greedily adding colors in growing amounts.
+    Yliluoma2,
+    /// Thomas Knoll's pattern dither, picking the nearest color to the input
+    /// corrected by the error of the colors picked so far.
+    Knoll,
+}
+
+/// Fraction of the error accumulated so far that Knoll's method corrects the
+/// next candidate by.
+const KNOLL_ERROR_MULTIPLIER: f32 = 0.5;
+
+/// Most plans a PlanCache holds before it is emptied, bounding its memory.
+const MAX_CACHED_PLANS: usize = 1 << 16;
+
+/// Mixing plans by the color they were made for, kept across frames as the
+/// colors of a video mostly repeat from one frame to the next. The plans are
+/// only valid for the method, palette and plan size they were made with, so a
+/// clone starts out empty.
+#[derive(Debug, Default)]
+pub struct PlanCache(Mutex<HashMap<(u32, u32, u32), Vec<usize>>>);
+
+impl PlanCache {
+    /// The cached plans, emptied first if there are more than
+    /// MAX_CACHED_PLANS.
+    pub fn plans(
+        &self,
+    ) -> MutexGuard<'_, HashMap<(u32, u32, u32), Vec<usize>>> {
+        let mut plans = self.0.lock().unwrap();
+        if plans.len() > MAX_CACHED_PLANS {
+            plans.clear();
+        }
+        plans
+    }
+}
+
+impl Clone for PlanCache {
+    fn clone(&self) -> Self {
+        PlanCache::default()
+    }
+}
+
+/// Mixing plan of size entries for rgb.
+pub fn mixing_plan(
+    method: PatternMethod,
+    palette: &Palette,
+    rgb: RgbPixel,
+    size: usize,
+) -> Vec<usize> {
+    assert!(size > 0);
+
+    let mut plan = match method {
+        PatternMethod::Yliluoma1 => yliluoma1(palette, rgb, size),
+        PatternMethod::Yliluoma2 => yliluoma2(palette, rgb, size),
+        PatternMethod::Knoll => knoll(palette, rgb, size),
+    };
+    plan.sort_by(|&a, &b| palette.luma(a).total_cmp(&palette.luma(b)));
+    plan
+}
+
+fn yliluoma1(palette: &Palette, rgb: RgbPixel, size: usize) -> Vec<usize> {
+    let steps = size as f32;
+    // Penalty, first color, second color and how many of size are the second.
+    let mut best = (f32::INFINITY, 0, 0, 0);
+
+    for a in 0..palette.len() {
+        for b in a..palette.len() {
+            let (ca, cb) = (palette.get(a), palette.get(b));
+            let span = distance(ca, cb);
+
+            // Ratio whose mix lies closest to rgb on the line from a to b.
+            let ratio = if span == 0.0 {
+                0.0
+            } else {
+                let dot = (rgb.0 - ca.0) * (cb.0 - ca.0)
+                    + (rgb.1 - ca.1) * (cb.1 - ca.1)
+                    + (rgb.2 - ca.2) * (cb.2 - ca.2);
+                ((dot / span).clamp(0.0, 1.0) * steps).round() / steps
+            };
+            let mix = (
+                ca.0 + (cb.0 - ca.0) * ratio,
+                ca.1 + (cb.1 - ca.1) * ratio,
+                ca.2 + (cb.2 - ca.2) * ratio,
+            );
+
+            // Penalize mixing far apart colors, more so in uneven ratios. Done
+            // on plain rather than squared distances, which would keep far
+            // apart colors from ever mixing. Colors mix in RGB, but are
+            // measured by the palette's metric.
+            let penalty = palette.difference(rgb, mix)
+                + palette.difference(ca, cb)
+                    * 0.1
+                    * ((ratio - 0.5).abs() + 0.5);
+            if penalty < best.0 {
+                best = (penalty, a, b, (ratio * steps) as usize);
+            }
+        }
+    }
+
+    let (_, a, b, count) = best;
+    let mut plan = vec![a; size - count];
+    plan.extend(std::iter::repeat_n(b, count));
+    plan
+}
+
+fn yliluoma2(palette: &Palette, rgb: RgbPixel, size: usize) -> Vec<usize> {
+    let mut plan = Vec::with_capacity(size);
+    let mut sum = (0.0, 0.0, 0.0);
+
+    while plan.len() < size {
+        let max_count = plan.len().max(1);
+        // Penalty, color and how many of it to add.
+        let mut best = (f32::INFINITY, 0, 1);
+
+        for i in 0..palette.len() {
+            let (r, g, b) = palette.get(i);
+            let mut count = 1;
+            while count <= max_count && plan.len() + count <= size {
+                let total = (plan.len() + count) as f32;
+                let n = count as f32;
+                let mix = (
+                    (sum.0 + r * n) / total,
+                    (sum.1 + g * n) / total,
+                    (sum.2 + b * n) / total,
+                );
+                let penalty = palette.difference(rgb, mix);
+                if penalty < best.0 {
+                    best = (penalty, i, count);
+                }
+                count *= 2;
+            }
+        }
+
+        let (_, i, count) = best;
+        let (r, g, b) = palette.get(i);
+        let n = count as f32;
+        sum = (sum.0 + r * n, sum.1 + g * n, sum.2 + b * n);
+        plan.extend(std::iter::repeat_n(i, count));
+    }
+
+    plan
+}
+
+fn knoll(palette: &Palette, rgb: RgbPixel, size: usize) -> Vec<usize> {
+    let mut plan = Vec::with_capacity(size);
+    let mut error = (0.0, 0.0, 0.0);
+
+    for _ in 0..size {
+        let attempt = (
+            (rgb.0 + error.0 * KNOLL_ERROR_MULTIPLIER).clamp(0.0, 255.0),
+            (rgb.1 + error.1 * KNOLL_ERROR_MULTIPLIER).clamp(0.0, 255.0),
+            (rgb.2 + error.2 * KNOLL_ERROR_MULTIPLIER).clamp(0.0, 255.0),
+        );
+        let chosen = palette.nearest(attempt);
+        let (r, g, b) = palette.get(chosen);
+        error = (
+            error.0 + rgb.0 - r,
+            error.1 + rgb.1 - g,
+            error.2 + rgb.2 - b,
+        );
+        plan.push(chosen);
+    }
+
+    plan
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::color::ColorMetric;
+
+    const METHODS: [PatternMethod; 3] = [
+        PatternMethod::Yliluoma1,
+        PatternMethod::Yliluoma2,
+        PatternMethod::Knoll,
+    ];
+
+    fn average(palette: &Palette, plan: &[usize]) -> RgbPixel {
+        let n = plan.len() as f32;
+        plan.iter()
+            .map(|&i| palette.get(i))
+            .fold((0.0, 0.0, 0.0), |(r, g, b), (pr, pg, pb)| {
+                (r + pr / n, g + pg / n, b + pb / n)
+            })
+    }
+
+    #[test]
+    fn plans_average_to_the_color() {
+        let palette =
+            Palette::parse("#000000 #ffffff #ff0000 #0000ff").unwrap();
+        for method in METHODS {
+            for rgb in [(64.0, 64.0, 64.0), (160.0, 0.0, 96.0)] {
+                let plan = mixing_plan(method, &palette, rgb, 64);
+                assert_eq!(plan.len(), 64);
+                let mix = average(&palette, &plan);
+                assert!(
+                    distance(rgb, mix).sqrt() < 20.0,
+                    "{:?} mixed {:?} for {:?}",
+                    method,
+                    mix,
+                    rgb
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn yliluoma_mixes_by_the_palette_metric() {
+        let rgb_palette =
+            Palette::parse("#000000 #ffffff #ff0000 #0000ff").unwrap();
+        let palette = rgb_palette.clone().with_metric(ColorMetric::Ciede2000);
+        let rgb = (200.0, 180.0, 20.0);
+        for method in [PatternMethod::Yliluoma1, PatternMethod::Yliluoma2] {
+            let by_metric = mixing_plan(method, &palette, rgb, 64);
+            let by_rgb = mixing_plan(method, &rgb_palette, rgb, 64);
+            assert!(
+                palette.difference(rgb, average(&palette, &by_metric))
+                    < palette.difference(rgb, average(&palette, &by_rgb)),
+                "{:?}",
+                method
+            );
+        }
+    }
+
+    #[test]
+    fn plans_are_ordered_by_luma() {
+        let palette = Palette::parse("#ffffff #000000 #808080").unwrap();
+        for method in METHODS {
+            let plan = mixing_plan(method, &palette, (100.0, 100.0, 100.0), 16);
+            assert!(
+                plan.windows(2)
+                    .all(|w| palette.luma(w[0]) <= palette.luma(w[1])),
+                "{:?}",
+                method
+            );
+        }
+    }
+
+    #[test]
+    fn palette_colors_are_not_mixed() {
+        let palette = Palette::parse("#102030 #ffeedd #5080a0").unwrap();
+        for method in METHODS {
+            for i in 0..palette.len() {
+                let plan = mixing_plan(method, &palette, palette.get(i), 16);
+                assert!(plan.iter().all(|&p| p == i), "{:?}", method);
+            }
+        }
+    }
+
+    #[test]
+    fn plan_cache_clones_empty() {
+        let cache = PlanCache::default();
+        cache.plans().insert((0, 0, 0), vec![0]);
+
+        assert_eq!(cache.plans().len(), 1);
+        assert!(cache.clone().plans().is_empty());
+    }
+}
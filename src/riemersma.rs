@@ -0,0 +1,128 @@
+use std::collections::VecDeque;
+
+use crate::frame::RgbPixel;
+
+/// Coordinates of a width x height frame in the order of a Hilbert curve
+/// covering the smallest power of two square around it. Points outside of the
+/// frame are skipped, so every pixel is visited exactly once.
+pub fn hilbert_curve(
+    width: isize,
+    height: isize,
+) -> impl Iterator<Item = (isize, isize)> {
+    let side = (width.max(height).max(1) as usize).next_power_of_two();
+
+    (0..side * side)
+        .map(move |d| hilbert_point(side, d))
+        .filter(move |&(x, y)| x < width && y < height)
+}
+
+/// Point at distance d along the Hilbert curve filling a side x side square.
+fn hilbert_point(side: usize, d: usize) -> (isize, isize) {
+    let (mut x, mut y) = (0usize, 0usize);
+    let mut t = d;
+    let mut s = 1;
+    while s < side {
+        let rx = 1 & (t / 2);
+        let ry = 1 & (t ^ rx);
+        if ry == 0 {
+            if rx == 1 {
+                x = s - 1 - x;
+                y = s - 1 - y;
+            }
+            std::mem::swap(&mut x, &mut y);
+        }
+        x += s * rx;
+        y += s * ry;
+        t /= 4;
+        s *= 2;
+    }
+
+    (x as isize, y as isize)
+}
+
+/// The most recent quantization errors along the curve, weighted so that the
+/// newest has a weight of 1 and older ones decay exponentially down to ratio.
+pub struct ErrorQueue {
+    errors: VecDeque<RgbPixel>,
+    weights: Vec<f32>,
+}
+
+impl ErrorQueue {
+    pub fn new(length: usize, ratio: f32) -> Self {
+        // A single error is added back in full, so output alternates around
+        // mid-gray instead of averaging to the input.
+        assert!(length > 1);
+        assert!(0.0 < ratio && ratio <= 1.0);
+
+        let weights = (0..length)
+            .map(|i| ratio.powf((length - 1 - i) as f32 / (length - 1) as f32))
+            .collect();
+
+        ErrorQueue {
+            errors: VecDeque::from(vec![(0.0, 0.0, 0.0); length]),
+            weights,
+        }
+    }
+
+    pub fn weighted_error(&self) -> RgbPixel {
+        self.errors.iter().zip(self.weights.iter()).fold(
+            (0.0, 0.0, 0.0),
+            |(r, g, b), (&(er, eg, eb), &w)| {
+                (r + er * w, g + eg * w, b + eb * w)
+            },
+        )
+    }
+
+    /// Push the newest error, dropping the oldest.
+    pub fn push(&mut self, error: RgbPixel) {
+        self.errors.pop_front();
+        self.errors.push_back(error);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hilbert_curve_visits_every_pixel_once() {
+        for (width, height) in [(1, 1), (3, 5), (8, 8), (17, 4), (0, 3)] {
+            let mut points: Vec<(isize, isize)> =
+                hilbert_curve(width, height).collect();
+            points.sort();
+
+            let mut expected = Vec::new();
+            for x in 0..width {
+                for y in 0..height {
+                    expected.push((x, y));
+                }
+            }
+            assert_eq!(points, expected, "{}x{}", width, height);
+        }
+    }
+
+    #[test]
+    fn hilbert_curve_only_steps_to_neighbors_in_a_square() {
+        let points: Vec<(isize, isize)> = hilbert_curve(16, 16).collect();
+        for pair in points.windows(2) {
+            let (ax, ay) = pair[0];
+            let (bx, by) = pair[1];
+            assert_eq!((ax - bx).abs() + (ay - by).abs(), 1);
+        }
+    }
+
+    #[test]
+    fn error_queue_weights_decay_to_ratio() {
+        let mut queue = ErrorQueue::new(4, 1.0 / 8.0);
+        queue.push((8.0, 0.0, 0.0));
+        assert_eq!(queue.weighted_error(), (8.0, 0.0, 0.0));
+
+        for _ in 0..3 {
+            queue.push((0.0, 0.0, 0.0));
+        }
+        assert!((queue.weighted_error().0 - 1.0).abs() < 1e-5);
+
+        queue.push((0.0, 0.0, 0.0));
+        assert_eq!(queue.weighted_error(), (0.0, 0.0, 0.0));
+    }
+}
@@ -0,0 +1,198 @@
+use std::fmt::Write;
+
+/// Bins of each channel's histogram.
+const BINS: usize = 32;
+
+/// Options of scene cut detection.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SceneOpts {
+    /// Consecutive frames are a cut when their color histograms differ by
+    /// more than this fraction of their pixels.
+    pub threshold: f32,
+    /// Where to write the detected cuts as JSON.
+    pub report_path: Option<String>,
+}
+
+/// Where a new scene starts.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Cut {
+    /// Index of the first frame of the new scene.
+    pub frame_index: u64,
+    /// How much the histogram of the frame differs from the previous one's.
+    pub difference: f32,
+}
+
+/// Finds cuts between consecutive RGB24 frames by comparing their color
+/// histograms, which unlike comparing the pixels themselves doesn't mistake
+/// motion within a scene for a cut.
+pub struct SceneDetector {
+    threshold: f32,
+    histogram: Vec<u32>,
+    /// Histogram of the previous frame, empty before the first frame.
+    previous: Vec<u32>,
+}
+
+impl SceneDetector {
+    pub fn new(threshold: f32) -> Self {
+        SceneDetector {
+            threshold,
+            histogram: Vec::new(),
+            previous: Vec::new(),
+        }
+    }
+
+    /// Fraction of pixels in [0, 1] whose colors would have to change to turn
+    /// the previous frame's histogram into frame's, None for the first frame.
+    pub fn difference(&mut self, frame: &[u8]) -> Option<f32> {
+        self.histogram.clear();
+        self.histogram.resize(BINS * 3, 0);
+        for pixel in frame.chunks_exact(3) {
+            for (c, &value) in pixel.iter().enumerate() {
+                self.histogram[c * BINS + value as usize * BINS / 256] += 1;
+            }
+        }
+
+        let difference = (!self.previous.is_empty()).then(|| {
+            let changed: u32 = self
+                .histogram
+                .iter()
+                .zip(&self.previous)
+                .map(|(&a, &b)| a.abs_diff(b))
+                .sum();
+            // Every changed pixel moves out of one bin and into another, on
+            // each of the 3 channels.
+            changed as f32 / (frame.len() as f32 * 2.0).max(1.0)
+        });
+        std::mem::swap(&mut self.histogram, &mut self.previous);
+        difference
+    }
+
+    /// Cut at frame if it differs from the previous frame beyond the
+    /// threshold.
+    pub fn detect(&mut self, frame_index: u64, frame: &[u8]) -> Option<Cut> {
+        self.difference(frame)
+            .filter(|&difference| difference > self.threshold)
+            .map(|difference| Cut {
+                frame_index,
+                difference,
+            })
+    }
+}
+
+/// JSON report of cuts in a video of frame_rate, detected with threshold.
+/// Frame rate and times are null when the frame rate isn't known.
+pub fn report_json(cuts: &[Cut], threshold: f32, frame_rate: f32) -> String {
+    let known_rate = frame_rate.is_finite() && frame_rate > 0.0;
+    let mut json = String::new();
+    writeln!(json, "{{").unwrap();
+    writeln!(json, "  \"threshold\": {},", threshold).unwrap();
+    if known_rate {
+        writeln!(json, "  \"frame_rate\": {},", frame_rate).unwrap();
+    } else {
+        writeln!(json, "  \"frame_rate\": null,").unwrap();
+    }
+    write!(json, "  \"cuts\": [").unwrap();
+    for (i, cut) in cuts.iter().enumerate() {
+        let separator = if i == 0 { "" } else { "," };
+        let time = if known_rate {
+            format!("{:.3}", cut.frame_index as f32 / frame_rate)
+        } else {
+            "null".to_string()
+        };
+        write!(
+            json,
+            "{}\n    {{\"frame\": {}, \"time\": {}, \"difference\": {:.4}}}",
+            separator, cut.frame_index, time, cut.difference
+        )
+        .unwrap();
+    }
+    if !cuts.is_empty() {
+        write!(json, "\n  ").unwrap();
+    }
+    writeln!(json, "]").unwrap();
+    writeln!(json, "}}").unwrap();
+    json
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A 16 x 16 RGB24 frame of a gradient between from and to.
+    fn gradient(from: [u8; 3], to: [u8; 3], shift: usize) -> Vec<u8> {
+        (0..256)
+            .flat_map(|i| {
+                let t = ((i + shift) % 256) as f32 / 255.0;
+                (0..3).map(move |c| {
+                    (from[c] as f32 + (to[c] as f32 - from[c] as f32) * t) as u8
+                })
+            })
+            .collect()
+    }
+
+    #[test]
+    fn difference_is_fraction_of_changed_pixels() {
+        let mut detector = SceneDetector::new(0.5);
+        let mut frame = vec![0; 4 * 3];
+        assert_eq!(detector.difference(&frame), None);
+        assert_eq!(detector.difference(&frame), Some(0.0));
+
+        frame[..3].fill(255);
+        assert_eq!(detector.difference(&frame), Some(0.25));
+        assert_eq!(detector.difference(&[255; 12]), Some(0.75));
+    }
+
+    #[test]
+    fn detect_cuts_between_different_shots_only() {
+        let mut detector = SceneDetector::new(0.4);
+        let dark = |shift| gradient([0, 0, 40], [80, 60, 120], shift);
+        let bright = gradient([200, 180, 20], [255, 255, 160], 0);
+
+        assert_eq!(detector.detect(0, &dark(0)), None);
+        // Motion within a scene moves few pixels between bins.
+        assert_eq!(detector.detect(1, &dark(3)), None);
+        let cut = detector.detect(2, &bright).unwrap();
+        assert_eq!(cut.frame_index, 2);
+        assert!(cut.difference > 0.7);
+        assert_eq!(detector.detect(3, &bright), None);
+    }
+
+    #[test]
+    fn report_json_lists_cuts() {
+        let cuts = [
+            Cut {
+                frame_index: 48,
+                difference: 0.75,
+            },
+            Cut {
+                frame_index: 120,
+                difference: 0.5,
+            },
+        ];
+        assert_eq!(
+            report_json(&cuts, 0.4, 24.0),
+            "{\n  \"threshold\": 0.4,\n  \"frame_rate\": 24,\n  \"cuts\": [\n    \
+             {\"frame\": 48, \"time\": 2.000, \"difference\": 0.7500},\n    \
+             {\"frame\": 120, \"time\": 5.000, \"difference\": 0.5000}\n  ]\n}\n"
+        );
+        assert_eq!(
+            report_json(&[], 0.4, 24.0),
+            "{\n  \"threshold\": 0.4,\n  \"frame_rate\": 24,\n  \"cuts\": []\n}\n"
+        );
+    }
+
+    #[test]
+    fn report_json_has_no_times_without_frame_rate() {
+        let cuts = [Cut {
+            frame_index: 48,
+            difference: 0.75,
+        }];
+        for frame_rate in [0.0, -1.0, f32::NAN, f32::INFINITY] {
+            assert_eq!(
+                report_json(&cuts, 0.4, frame_rate),
+                "{\n  \"threshold\": 0.4,\n  \"frame_rate\": null,\n  \"cuts\": [\n    \
+                 {\"frame\": 48, \"time\": null, \"difference\": 0.7500}\n  ]\n}\n"
+            );
+        }
+    }
+}
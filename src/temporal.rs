@@ -0,0 +1,284 @@
+use crate::motion::{MotionField, MotionOpts};
+
+/// Options of temporal stabilization, which keeps static regions of a video
+/// dithered the same from frame to frame instead of shimmering.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TemporalOpts {
+    /// A pixel keeps its previous output while no channel of its source has
+    /// changed by more than this since it was last dithered.
+    pub tolerance: u8,
+    /// Fraction of every refreshed pixel's quantization error added to it in
+    /// the next frame, so pixels average out to their source over time.
+    pub carry_error: f32,
+    /// Follow motion between frames, so the output and error of moving
+    /// regions move along with them instead of being dithered anew.
+    pub motion: Option<MotionOpts>,
+}
+
+/// What is carried from one frame of a video to the next. Call prepare on
+/// every source frame before dithering it and stabilize on the dithered
+/// frame after.
+pub struct TemporalState {
+    opts: TemporalOpts,
+    width: usize,
+    height: usize,
+    /// Source of the frame being dithered.
+    source: Vec<u8>,
+    /// Source of every pixel when its output was last refreshed.
+    reference: Vec<u8>,
+    /// Previous output, empty before the first frame.
+    output: Vec<u8>,
+    /// Quantization error of the pixels refreshed in the previous frame, and
+    /// zero for pixels that kept their output.
+    error: Vec<f32>,
+}
+
+impl TemporalState {
+    pub fn new(opts: TemporalOpts, width: usize, height: usize) -> Self {
+        TemporalState {
+            opts,
+            width,
+            height,
+            source: Vec::new(),
+            reference: Vec::new(),
+            output: Vec::new(),
+            error: Vec::new(),
+        }
+    }
+
+    /// Forget previous frames, like at a scene cut, so the next frame is
+    /// dithered from scratch.
+    pub fn reset(&mut self) {
+        self.reference.clear();
+        self.output.clear();
+        self.error.clear();
+    }
+
+    /// Remember the source of an RGB24 frame about to be dithered, move what
+    /// is carried from the previous frame along the motion between them and
+    /// add the carried error to it.
+    pub fn prepare(&mut self, frame: &mut [u8]) {
+        assert!(frame.len() == self.width * self.height * 3);
+
+        if frame.len() != self.output.len() {
+            self.reset();
+        }
+        if let Some(motion) = self.opts.motion
+            && !self.output.is_empty()
+        {
+            let field = MotionField::estimate(
+                &self.source,
+                frame,
+                self.width,
+                self.height,
+                motion,
+            );
+            self.reference = field.warp(&self.reference);
+            self.output = field.warp(&self.output);
+            if !self.error.is_empty() {
+                self.error = field.warp(&self.error);
+            }
+        }
+        self.source.clear();
+        self.source.extend_from_slice(frame);
+
+        if self.opts.carry_error > 0.0 && !self.error.is_empty() {
+            for (value, &error) in frame.iter_mut().zip(&self.error) {
+                let carried = *value as f32 + error * self.opts.carry_error;
+                *value = carried.round().clamp(0.0, 255.0) as u8;
+            }
+        }
+    }
+
+    /// Replace pixels of the dithered frame whose source hasn't changed beyond
+    /// the tolerance with their previous output.
+    pub fn stabilize(&mut self, frame: &mut [u8]) {
+        assert!(frame.len() == self.source.len());
+
+        let carry = self.opts.carry_error > 0.0;
+        if carry {
+            self.error.resize(frame.len(), 0.0);
+        }
+        let first = self.output.is_empty();
+        if first {
+            self.reference.clone_from(&self.source);
+        }
+        for i in (0..frame.len()).step_by(3) {
+            let changed = first
+                || (i..i + 3).any(|c| {
+                    self.source[c].abs_diff(self.reference[c])
+                        > self.opts.tolerance
+                });
+            if changed {
+                self.reference[i..i + 3]
+                    .copy_from_slice(&self.source[i..i + 3]);
+            } else {
+                frame[i..i + 3].copy_from_slice(&self.output[i..i + 3]);
+            }
+            // Only error of the source against fresh output is carried, so
+            // held pixels don't build up error they never dither away.
+            if carry {
+                let errors = self.error[i..i + 3]
+                    .iter_mut()
+                    .zip(&self.source[i..i + 3])
+                    .zip(&frame[i..i + 3]);
+                for ((error, &source), &output) in errors {
+                    *error = if changed {
+                        source as f32 - output as f32
+                    } else {
+                        0.0
+                    };
+                }
+            }
+        }
+
+        self.output.clear();
+        self.output.extend_from_slice(frame);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn state(tolerance: u8, carry_error: f32) -> TemporalState {
+        TemporalState::new(
+            TemporalOpts {
+                tolerance,
+                carry_error,
+                motion: None,
+            },
+            2,
+            1,
+        )
+    }
+
+    /// Run a frame through state, with dithered standing in for dithering.
+    fn step(
+        state: &mut TemporalState,
+        source: &[u8],
+        dithered: &[u8],
+    ) -> Vec<u8> {
+        let mut frame = source.to_vec();
+        state.prepare(&mut frame);
+        frame.copy_from_slice(dithered);
+        state.stabilize(&mut frame);
+        frame
+    }
+
+    #[test]
+    fn static_pixels_keep_their_output() {
+        let mut state = state(4, 0.0);
+        let source = [100, 100, 100, 200, 200, 200];
+        assert_eq!(
+            step(&mut state, &source, &[0, 0, 0, 255, 255, 255]),
+            [0, 0, 0, 255, 255, 255]
+        );
+
+        let source = [102, 100, 100, 100, 100, 100];
+        assert_eq!(
+            step(&mut state, &source, &[255, 255, 255, 0, 0, 0]),
+            [0, 0, 0, 0, 0, 0]
+        );
+    }
+
+    #[test]
+    fn slow_drift_refreshes_once_beyond_tolerance() {
+        let mut state = state(4, 0.0);
+        step(&mut state, &[100, 100, 100, 0, 0, 0], &[0, 0, 0, 0, 0, 0]);
+        assert_eq!(
+            step(&mut state, &[103, 100, 100, 0, 0, 0], &[255, 0, 0, 0, 0, 0]),
+            [0, 0, 0, 0, 0, 0]
+        );
+        assert_eq!(
+            step(&mut state, &[106, 100, 100, 0, 0, 0], &[255, 0, 0, 0, 0, 0]),
+            [255, 0, 0, 0, 0, 0]
+        );
+    }
+
+    #[test]
+    fn reset_refreshes_every_pixel() {
+        let mut state = state(255, 0.0);
+        step(&mut state, &[100; 6], &[0; 6]);
+        state.reset();
+        assert_eq!(step(&mut state, &[100; 6], &[255; 6]), [255; 6]);
+    }
+
+    #[test]
+    fn carried_error_is_added_to_the_next_frame() {
+        let mut state = state(0, 0.5);
+        step(&mut state, &[100; 6], &[0; 6]);
+
+        let mut frame = vec![100; 6];
+        state.prepare(&mut frame);
+        assert_eq!(frame, [150; 6]);
+    }
+
+    #[test]
+    fn output_of_moving_regions_follows_them() {
+        let (width, height) = (32, 32);
+        let mut state = TemporalState::new(
+            TemporalOpts {
+                tolerance: 4,
+                carry_error: 0.0,
+                motion: Some(MotionOpts {
+                    block_size: 8,
+                    search_range: 4,
+                }),
+            },
+            width,
+            height,
+        );
+        // A bright square moving right by 3 pixels on a dark background.
+        let frame = |left: usize| -> Vec<u8> {
+            (0..width * height)
+                .flat_map(|i| {
+                    let (x, y) = (i % width, i / width);
+                    let inside =
+                        (left..left + 12).contains(&x) && (10..22).contains(&y);
+                    [if inside { 200 } else { 20 }; 3]
+                })
+                .collect()
+        };
+        // Dithering stand-in with a pattern fixed to the frame, not the square.
+        let fixed_pattern: Vec<u8> = (0..width * height * 3)
+            .map(|i| if (i / 3) % 2 == 0 { 255 } else { 0 })
+            .collect();
+
+        let first = step(&mut state, &frame(8), &fixed_pattern);
+        let second = step(&mut state, &frame(11), &fixed_pattern);
+        for y in 10..22 {
+            for x in 11..23 {
+                let i = (y * width + x) * 3;
+                let j = i - 3 * 3;
+                assert_eq!(second[i..i + 3], first[j..j + 3]);
+            }
+        }
+    }
+
+    #[test]
+    fn held_pixels_carry_no_error() {
+        let mut state = state(4, 0.5);
+        step(&mut state, &[100; 6], &[0; 6]);
+
+        for held in 0..4 {
+            let mut frame = vec![100; 6];
+            state.prepare(&mut frame);
+            assert_eq!(frame, if held == 0 { [150; 6] } else { [100; 6] });
+            frame.copy_from_slice(&[255; 6]);
+            state.stabilize(&mut frame);
+            assert_eq!(frame, [0; 6]);
+        }
+
+        let mut frame = vec![200; 6];
+        state.prepare(&mut frame);
+        assert_eq!(frame, [200; 6]);
+        frame.copy_from_slice(&[255; 6]);
+        state.stabilize(&mut frame);
+        assert_eq!(frame, [255; 6]);
+
+        let mut frame = vec![200; 6];
+        state.prepare(&mut frame);
+        assert_eq!(frame, [173; 6]);
+    }
+}
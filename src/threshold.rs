@@ -0,0 +1,282 @@
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+use crate::ffmpeg;
+
+/// A matrix of thresholds in (0, 1) that is tiled over a frame by ordered
+/// dithering.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ThresholdMatrix {
+    pub width: usize,
+    pub height: usize,
+    values: Vec<f32>,
+}
+
+impl ThresholdMatrix {
+    /// Build a matrix from row-major ranks, where rank r of n cells becomes the
+    /// threshold (r + 0.5) / n. Ranks don't have to be unique.
+    pub fn from_ranks(width: usize, height: usize, ranks: &[usize]) -> Self {
+        assert!(width > 0 && height > 0);
+        assert!(ranks.len() == width * height);
+
+        let count = ranks.len() as f32;
+        ThresholdMatrix {
+            width,
+            height,
+            values: ranks.iter().map(|&r| (r as f32 + 0.5) / count).collect(),
+        }
+    }
+
+    /// Recursively generated size x size Bayer matrix, size must be a power of
+    /// two.
+    pub fn bayer(size: usize) -> Self {
+        assert!(size.is_power_of_two());
+
+        let mut ranks = vec![0usize];
+        let mut n = 1;
+        while n < size {
+            let mut next = vec![0usize; 4 * n * n];
+            for y in 0..n {
+                for x in 0..n {
+                    let rank = 4 * ranks[y * n + x];
+                    next[y * 2 * n + x] = rank;
+                    next[y * 2 * n + x + n] = rank + 2;
+                    next[(y + n) * 2 * n + x] = rank + 3;
+                    next[(y + n) * 2 * n + x + n] = rank + 1;
+                }
+            }
+            ranks = next;
+            n *= 2;
+        }
+
+        Self::from_ranks(size, size, &ranks)
+    }
+
+    /// Generate a size x size tileable blue-noise mask with the void-and-cluster
+    /// method. The same seed always generates the same mask.
+    pub fn blue_noise(size: usize, seed: u64) -> Self {
+        let count = size * size;
+        let mut rng = StdRng::seed_from_u64(seed);
+        let mut pattern = BinaryPattern::new(size);
+
+        // Start from sparse white noise, then move its tightest clusters into
+        // its largest voids until it is evenly distributed.
+        let initial_count = (count / 10).max(1);
+        while pattern.ones < initial_count {
+            let i = rng.random_range(0..count);
+            if !pattern.bits[i] {
+                pattern.toggle(i);
+            }
+        }
+        for _ in 0..count {
+            let cluster = pattern.tightest_cluster();
+            pattern.toggle(cluster);
+            let void = pattern.largest_void();
+            pattern.toggle(void);
+            if cluster == void {
+                break;
+            }
+        }
+
+        let mut ranks = vec![0usize; count];
+
+        // Ranks below the initial pattern are given by removing its tightest
+        // clusters one by one...
+        let mut removing = pattern.clone();
+        for rank in (0..initial_count).rev() {
+            let cluster = removing.tightest_cluster();
+            removing.toggle(cluster);
+            ranks[cluster] = rank;
+        }
+        // ...and the ones above it by filling its largest voids.
+        for rank in initial_count..count {
+            let void = pattern.largest_void();
+            pattern.toggle(void);
+            ranks[void] = rank;
+        }
+
+        Self::from_ranks(size, size, &ranks)
+    }
+
+    /// Use a grayscale image as the mask, where darker pixels have lower
+    /// thresholds.
+    pub fn load(path: &str) -> Result<Self, String> {
+        let (width, height, pixels) = ffmpeg::read_gray_image(path)?;
+        if width == 0 || height == 0 {
+            return Err(format!("Mask '{}' is empty", path));
+        }
+
+        Ok(ThresholdMatrix {
+            width,
+            height,
+            values: pixels.iter().map(|&v| (v as f32 + 0.5) / 256.0).collect(),
+        })
+    }
+
+    /// Threshold at x, y of a frame with this matrix tiled over it.
+    pub fn get(&self, x: isize, y: isize) -> f32 {
+        let mx = x.rem_euclid(self.width as isize) as usize;
+        let my = y.rem_euclid(self.height as isize) as usize;
+        self.values[my * self.width + mx]
+    }
+}
+
+/// A toroidal binary pattern that tracks, for every cell, the Gaussian weighted
+/// density of set cells around it.
+#[derive(Clone)]
+struct BinaryPattern {
+    size: usize,
+    bits: Vec<bool>,
+    ones: usize,
+    energy: Vec<f32>,
+    /// Gaussian weight of every toroidal offset, indexed like bits.
+    gaussian: Vec<f32>,
+}
+
+impl BinaryPattern {
+    const SIGMA: f32 = 1.5;
+
+    fn new(size: usize) -> Self {
+        let mut gaussian = vec![0.0; size * size];
+        for dy in 0..size {
+            for dx in 0..size {
+                let wx = dx.min(size - dx) as f32;
+                let wy = dy.min(size - dy) as f32;
+                gaussian[dy * size + dx] =
+                    (-(wx * wx + wy * wy) / (2.0 * Self::SIGMA.powi(2))).exp();
+            }
+        }
+
+        BinaryPattern {
+            size,
+            bits: vec![false; size * size],
+            ones: 0,
+            energy: vec![0.0; size * size],
+            gaussian,
+        }
+    }
+
+    fn toggle(&mut self, i: usize) {
+        self.bits[i] = !self.bits[i];
+        let sign = if self.bits[i] { 1.0 } else { -1.0 };
+        if self.bits[i] {
+            self.ones += 1;
+        } else {
+            self.ones -= 1;
+        }
+
+        let (x, y) = (i % self.size, i / self.size);
+        for ny in 0..self.size {
+            let dy = (ny + self.size - y) % self.size;
+            for nx in 0..self.size {
+                let dx = (nx + self.size - x) % self.size;
+                self.energy[ny * self.size + nx] +=
+                    sign * self.gaussian[dy * self.size + dx];
+            }
+        }
+    }
+
+    /// Set cell with the most set cells around it.
+    fn tightest_cluster(&self) -> usize {
+        self.extreme(true, |a, b| a > b)
+    }
+
+    /// Unset cell with the least set cells around it.
+    fn largest_void(&self) -> usize {
+        self.extreme(false, |a, b| a < b)
+    }
+
+    fn extreme(&self, bit: bool, better: impl Fn(f32, f32) -> bool) -> usize {
+        let mut best: Option<usize> = None;
+        for i in 0..self.bits.len() {
+            if self.bits[i] == bit
+                && best.is_none_or(|b| better(self.energy[i], self.energy[b]))
+            {
+                best = Some(i);
+            }
+        }
+        best.expect("Expected at least one cell to match")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ranks(matrix: &ThresholdMatrix) -> Vec<usize> {
+        let count = (matrix.width * matrix.height) as f32;
+        matrix
+            .values
+            .iter()
+            .map(|&v| (v * count - 0.5).round() as usize)
+            .collect()
+    }
+
+    #[test]
+    fn bayer_2x2_matches_reference() {
+        assert_eq!(ranks(&ThresholdMatrix::bayer(2)), vec![0, 2, 3, 1]);
+    }
+
+    #[test]
+    fn bayer_4x4_matches_reference() {
+        assert_eq!(
+            ranks(&ThresholdMatrix::bayer(4)),
+            vec![0, 8, 2, 10, 12, 4, 14, 6, 3, 11, 1, 9, 15, 7, 13, 5]
+        );
+    }
+
+    #[test]
+    fn bayer_ranks_are_a_permutation() {
+        for size in [2, 4, 8, 16] {
+            let mut ranks = ranks(&ThresholdMatrix::bayer(size));
+            ranks.sort();
+            assert_eq!(ranks, (0..size * size).collect::<Vec<usize>>());
+        }
+    }
+
+    #[test]
+    fn get_wraps_around() {
+        let matrix = ThresholdMatrix::bayer(4);
+        assert_eq!(matrix.get(5, 6), matrix.get(1, 2));
+        assert_eq!(matrix.get(-1, -4), matrix.get(3, 0));
+    }
+
+    #[test]
+    fn blue_noise_ranks_are_a_permutation() {
+        let mut ranks = ranks(&ThresholdMatrix::blue_noise(16, 0));
+        ranks.sort();
+        assert_eq!(ranks, (0..16 * 16).collect::<Vec<usize>>());
+    }
+
+    #[test]
+    fn blue_noise_is_deterministic_per_seed() {
+        assert_eq!(
+            ThresholdMatrix::blue_noise(16, 7),
+            ThresholdMatrix::blue_noise(16, 7)
+        );
+        assert_ne!(
+            ThresholdMatrix::blue_noise(16, 7),
+            ThresholdMatrix::blue_noise(16, 8)
+        );
+    }
+
+    #[test]
+    fn blue_noise_spreads_out_sparse_thresholds() {
+        // At 1/8 density, no two lit pixels of a blue-noise pattern should
+        // touch, even across the edges of the tile.
+        let size = 32;
+        let matrix = ThresholdMatrix::blue_noise(size, 0);
+        let lit = |x: isize, y: isize| matrix.get(x, y) < 1.0 / 8.0;
+
+        for y in 0..size as isize {
+            for x in 0..size as isize {
+                if !lit(x, y) {
+                    continue;
+                }
+                for (dx, dy) in [(1, 0), (-1, 1), (0, 1), (1, 1)] {
+                    assert!(!lit(x + dx, y + dy), "({}, {})", x, y);
+                }
+            }
+        }
+    }
+}